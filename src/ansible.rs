@@ -0,0 +1,330 @@
+use cotton::prelude::result::Result as PResult;
+use cotton::prelude::*;
+
+use crate::editor::EditStatus;
+use crate::pipeline::{prepare_edit, problem_message, write_edit};
+use crate::{Cli, Command};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Arguments of the module as passed by Ansible in JSON args file
+#[derive(Debug, Deserialize)]
+struct ModuleArgs {
+    /// File to edit
+    path: PathBuf,
+    /// Name of the edit subcommand, e.g. `line-pair`
+    edit: String,
+    /// Positional arguments of the edit subcommand
+    #[serde(default)]
+    value: Values,
+    /// Options of the edit subcommand by long name with `_` or `-` as word separator
+    #[serde(default)]
+    options: BTreeMap<String, Value>,
+    /// Either `present` or `absent`
+    #[serde(default = "default_state")]
+    state: String,
+    /// One of `at-end` (default), `at-top`, `before` or `after` (require `anchor`)
+    placement: Option<String>,
+    anchor: Option<String>,
+    #[serde(default)]
+    create: bool,
+    validate: Option<String>,
+    #[serde(default)]
+    lock: bool,
+    /// Character encoding of the file, e.g. `latin1`
+    encoding: Option<String>,
+    #[serde(rename = "_ansible_check_mode", default)]
+    check_mode: bool,
+    #[serde(rename = "_ansible_diff", default)]
+    diff: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Values {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Default for Values {
+    fn default() -> Values {
+        Values::Many(Vec::new())
+    }
+}
+
+fn default_state() -> String {
+    "present".to_owned()
+}
+
+/// Module result as expected by Ansible on standard output
+#[derive(Debug, Default, Serialize)]
+struct ModuleResult {
+    changed: bool,
+    failed: bool,
+    msg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<EditStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<Diff>,
+}
+
+#[derive(Debug, Serialize)]
+struct Diff {
+    before: String,
+    after: String,
+    before_header: String,
+    after_header: String,
+}
+
+/// Returns path of Ansible args file if it was given as the only argument
+///
+/// Only a file containing JSON object with `path` and `edit` keys is taken as args file so that a file in current
+/// directory named like a subcommand does not switch to Ansible mode.
+pub fn args_file(args: &[OsString]) -> Option<PathBuf> {
+    let path = match args {
+        [_, arg] => PathBuf::from(arg),
+        _ => return None,
+    };
+
+    let args = fs::read(&path).ok()?;
+    match serde_json::from_slice::<Value>(&args).ok()? {
+        Value::Object(args) if args.contains_key("path") && args.contains_key("edit") => Some(path),
+        _ => None,
+    }
+}
+
+/// Builds command line arguments equivalent to module arguments
+fn command_line(args: &ModuleArgs) -> PResult<Vec<String>> {
+    let mut command_line = vec!["pedit".to_owned(), "--in-place".to_owned()];
+    command_line.push(args.path.to_string_lossy().into_owned());
+    if args.create {
+        command_line.push("--create".to_owned());
+    }
+    if args.lock {
+        command_line.push("--lock".to_owned());
+    }
+    if let Some(encoding) = &args.encoding {
+        command_line.push("--encoding".to_owned());
+        command_line.push(encoding.clone());
+    }
+    if let Some(validate) = &args.validate {
+        command_line.push("--validate".to_owned());
+        command_line.push(validate.clone());
+    }
+
+    command_line.push(args.edit.clone());
+    for (name, value) in &args.options {
+        let option = format!("--{}", name.replace('_', "-"));
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                Value::Bool(true) => command_line.push(option.clone()),
+                Value::Bool(false) | Value::Null => (),
+                Value::String(value) => {
+                    command_line.push(option.clone());
+                    command_line.push(value.clone());
+                }
+                Value::Number(value) => {
+                    command_line.push(option.clone());
+                    command_line.push(value.to_string());
+                }
+                value => return problem!("Unsupported value of option {:?}: {}", name, value),
+            }
+        }
+    }
+    match &args.value {
+        Values::One(value) => command_line.push(value.clone()),
+        Values::Many(values) => command_line.extend(values.iter().cloned()),
+    }
+
+    command_line.push(args.state.clone());
+    if args.state == "present" {
+        match args.placement.as_deref().unwrap_or("at-end") {
+            placement @ "at-end" | placement @ "at-top" => command_line.push(placement.to_owned()),
+            relation @ "before" | relation @ "after" => {
+                let anchor = args
+                    .anchor
+                    .as_ref()
+                    .ok_or_problem("Placement before or after requires anchor")?;
+                command_line.push("relative-to".to_owned());
+                command_line.push(anchor.clone());
+                command_line.push(relation.to_owned());
+            }
+            placement => return problem!("Unsupported placement: {:?}", placement),
+        }
+    }
+
+    Ok(command_line)
+}
+
+/// Performs the edit described by module arguments honouring check and diff modes
+fn execute(args: &ModuleArgs) -> PResult<ModuleResult> {
+    let command_line = command_line(args)?;
+    debug!("Ansible module command line: {:?}", command_line);
+    let mut cli =
+        Cli::from_iter_safe(&command_line).map_err(|err| Problem::from_error(err.message))?;
+    cli.check = args.check_mode;
+    // Edited input is only kept for diff if requested; it is not printed
+    cli.diff = args.diff;
+
+    let edit = match &cli.command {
+        Command::Edit(edit) => edit,
+        _ => return problem!("Unsupported edit: {:?}", args.edit),
+    };
+
+    let pending = prepare_edit(&cli, edit, Some(&args.path), Some(&args.path))?;
+    let status = pending.outcome.status;
+    write_edit(&cli, Some(&args.path), Some(&args.path), &pending)?;
+
+    let header = args.path.display().to_string();
+    let after = pending.output_data;
+    Ok(ModuleResult {
+        changed: status.has_changed(),
+        msg: status.to_string(),
+        status: Some(status),
+        diff: pending.input_data.map(|before| Diff {
+            before,
+            after,
+            before_header: header.clone(),
+            after_header: header,
+        }),
+        ..Default::default()
+    })
+}
+
+/// Runs as Ansible module reading arguments from given JSON args file and printing result JSON
+pub fn run(args_file: &Path) -> FinalResult {
+    let result = fs::read_to_string(args_file)
+        .problem_while("reading Ansible args file")
+        .and_then(|args| {
+            serde_json::from_str::<ModuleArgs>(&args).problem_while("parsing Ansible args file")
+        })
+        .and_then(|args| execute(&args));
+
+    let (result, failed) = match result {
+        Ok(result) => (result, false),
+        Err(problem) => (
+            ModuleResult {
+                failed: true,
+                msg: problem_message(&problem),
+                ..Default::default()
+            },
+            true,
+        ),
+    };
+    println!("{}", serde_json::to_string(&result)?);
+
+    if failed {
+        Err(Problem::from_error(result.msg)).fatal()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_line() -> FinalResult {
+        let args: ModuleArgs = serde_json::from_str(
+            r#"{
+                "path": "/etc/ssh/sshd_config",
+                "edit": "line-pair",
+                "value": "PermitRootLogin no",
+                "options": {"separator": " ", "ignore_whitespace": true, "multikey": false},
+                "placement": "before",
+                "anchor": "^Match",
+                "validate": "sshd -t -f %s",
+                "_ansible_check_mode": true
+            }"#,
+        )?;
+        assert!(args.check_mode);
+        assert_eq!(
+            command_line(&args)?,
+            vec![
+                "pedit",
+                "--in-place",
+                "/etc/ssh/sshd_config",
+                "--validate",
+                "sshd -t -f %s",
+                "line-pair",
+                "--ignore-whitespace",
+                "--separator",
+                " ",
+                "PermitRootLogin no",
+                "present",
+                "relative-to",
+                "^Match",
+                "before"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_latin1() -> FinalResult {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("legacy.conf");
+        fs::write(&file, b"name=Fran\xe7ois\n")?;
+
+        let args: ModuleArgs = serde_json::from_value(serde_json::json!({
+            "path": file,
+            "edit": "line",
+            "value": "city=K\u{f6}ln",
+            "encoding": "latin1",
+            "_ansible_diff": true
+        }))?;
+        let result = execute(&args)?;
+        let content = fs::read(&file)?;
+
+        assert!(result.changed);
+        assert_eq!(content, b"name=Fran\xe7ois\ncity=K\xf6ln\n");
+        assert_eq!(result.diff.unwrap().before, "name=Fran\u{e7}ois\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_args_file() -> FinalResult {
+        let dir = tempfile::tempdir()?;
+        let args = dir.path().join("args");
+        let dump = dir.path().join("dump");
+        fs::write(&args, r#"{"path": "/etc/hosts", "edit": "line"}"#)?;
+        fs::write(&dump, "foo=bar\n")?;
+
+        let args_file =
+            |path: &Path| super::args_file(&[OsString::from("pedit"), OsString::from(path)]);
+        assert_eq!(args_file(&args), Some(args.clone()));
+        assert_eq!(args_file(&dump), None);
+        assert_eq!(args_file(&dir.path().join("missing")), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_check_mode() -> FinalResult {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("hosts");
+        fs::write(&file, "127.0.0.1 localhost\n")?;
+
+        let args: ModuleArgs = serde_json::from_value(serde_json::json!({
+            "path": file,
+            "edit": "line",
+            "value": "10.0.0.1 db",
+            "_ansible_check_mode": true,
+            "_ansible_diff": true
+        }))?;
+        let result = execute(&args)?;
+        let content = fs::read_to_string(&file)?;
+
+        assert!(result.changed);
+        assert!(!result.failed);
+        assert_eq!(content, "127.0.0.1 localhost\n");
+        let diff = result.diff.unwrap();
+        assert_eq!(diff.before, "127.0.0.1 localhost\n");
+        assert_eq!(diff.after, "127.0.0.1 localhost\n10.0.0.1 db\n");
+        Ok(())
+    }
+}
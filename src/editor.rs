@@ -1,8 +1,15 @@
 use cotton::prelude::*;
 use regex::Regex;
+use serde::{Serialize, Serializer};
 use structopt::StructOpt;
 
-#[derive(Debug, StructOpt)]
+/// Serializes regular expression as its pattern
+pub fn serialize_regex<S: Serializer>(regex: &Regex, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(regex.as_str())
+}
+
+#[derive(Debug, Clone, StructOpt, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Ensure {
     /// Ensure value is present in file
     Present {
@@ -13,13 +20,15 @@ pub enum Ensure {
     Absent,
 }
 
-#[derive(Debug, StructOpt)]
+#[derive(Debug, Clone, StructOpt, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Placement {
     /// Relative to existing anchor entry
     RelativeTo {
         #[structopt(flatten)]
         relation: AnchorRelation,
         /// Regular expression pattern matching anchor value
+        #[serde(serialize_with = "serialize_regex")]
         anchor: Regex,
     },
     /// At the top of the file
@@ -28,7 +37,8 @@ pub enum Placement {
     AtEnd,
 }
 
-#[derive(Debug, StructOpt)]
+#[derive(Debug, Clone, StructOpt, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum AnchorRelation {
     /// Before matching anchor entry or at the end of the file
     Before,
@@ -36,25 +46,27 @@ pub enum AnchorRelation {
     After,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub enum ReplaceStatus {
     AlreadyPresent,
     Replaced,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub enum PresentStatus {
     AlreadyPresent,
     InsertedPlacement,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub enum AbsentStatus {
     AlreadyAbsent,
     Removed,
 }
 
-#[derive(Debug)]
+/// Outcome of an edit; serializes as name of the inner status variant
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(untagged)]
 pub enum EditStatus {
     Replaced(ReplaceStatus),
     Present(PresentStatus),
@@ -81,12 +93,12 @@ impl From<AbsentStatus> for EditStatus {
 
 impl EditStatus {
     pub fn has_changed(&self) -> bool {
-        match self {
+        !matches!(
+            self,
             EditStatus::Replaced(ReplaceStatus::AlreadyPresent)
-            | EditStatus::Present(PresentStatus::AlreadyPresent)
-            | EditStatus::Absent(AbsentStatus::AlreadyAbsent) => false,
-            _ => true,
-        }
+                | EditStatus::Present(PresentStatus::AlreadyPresent)
+                | EditStatus::Absent(AbsentStatus::AlreadyAbsent)
+        )
     }
 }
 
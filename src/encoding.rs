@@ -0,0 +1,79 @@
+use cotton::prelude::result::Result as PResult;
+use cotton::prelude::*;
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::io::{self, Cursor};
+use std::str::FromStr;
+
+/// UTF-8 encoded byte order mark as saved at the start of files by some Windows editors
+pub const BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Character encoding of edited files; files are decoded on input and encoded back on output so editors work on text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    /// ISO-8859-1 where each byte maps to the Unicode code point of the same value so any file decodes and
+    /// encodes back byte for byte
+    Latin1,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(encoding: &str) -> Result<Encoding, String> {
+        match encoding.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            _ => Err(format!("unsupported encoding {:?}", encoding)),
+        }
+    }
+}
+
+impl Encoding {
+    /// Removes UTF-8 byte order mark from the start of input returning whether it was present so it can be written
+    /// back on output; inputs in other encodings are returned as they are
+    pub fn strip_bom(self, mut input: Box<dyn Read>) -> io::Result<(Box<dyn Read>, bool)> {
+        if self != Encoding::Utf8 {
+            return Ok((input, false));
+        }
+
+        let mut prefix = Vec::with_capacity(BOM.len());
+        input
+            .by_ref()
+            .take(BOM.len() as u64)
+            .read_to_end(&mut prefix)?;
+        if prefix == BOM {
+            Ok((input, true))
+        } else {
+            Ok((Box::new(Cursor::new(prefix).chain(input)), false))
+        }
+    }
+
+    pub fn decode(self, data: Vec<u8>) -> PResult<String> {
+        match self {
+            Encoding::Utf8 => Ok(String::from_utf8(data)?),
+            Encoding::Latin1 => Ok(data.into_iter().map(char::from).collect()),
+        }
+    }
+
+    /// Encodes text prefixing it with byte order mark if `bom` is set
+    pub fn encode(self, text: &str, bom: bool) -> PResult<Cow<'_, [u8]>> {
+        match self {
+            Encoding::Utf8 if bom => Ok(Cow::Owned([BOM, text.as_bytes()].concat())),
+            Encoding::Utf8 => Ok(Cow::Borrowed(text.as_bytes())),
+            Encoding::Latin1 => text
+                .chars()
+                .map(|c| {
+                    u8::try_from(c).map_err(|_| {
+                        Problem::from_error(format!(
+                            "Character {:?} can not be encoded as latin1",
+                            c
+                        ))
+                    })
+                })
+                .collect::<PResult<Vec<u8>>>()
+                .map(Cow::Owned),
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use crate::editor::*;
+use crate::lines_editor::{place_line, single_position, write_lines, LinesEditorError};
+
+use cotton::prelude::*;
+
+const EXPORT: &str = "export";
+
+/// Editor for shell-style environment files like `/etc/default/*` or `.env`
+#[derive(Debug)]
+pub struct EnvEditor {
+    lines: Vec<String>,
+}
+
+/// Variable assignment parsed from a line
+#[derive(Debug)]
+struct Assignment<'l> {
+    /// Indentation and optional `export` keyword
+    prefix: &'l str,
+    key: &'l str,
+    value: String,
+    /// Rest of the line after the value starting with white space, e.g. inline comment
+    trailing: &'l str,
+}
+
+fn valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .filter(|c| c.is_ascii_alphabetic() || *c == '_')
+        .is_some()
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_assignment(line: &str) -> Option<Assignment<'_>> {
+    let trimmed = line.trim_start();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return None;
+    }
+
+    let rest = match trimmed.strip_prefix(EXPORT) {
+        Some(rest) if rest.starts_with(|c: char| c.is_whitespace()) => rest.trim_start(),
+        _ => trimmed,
+    };
+    let prefix = &line[..line.len() - rest.len()];
+
+    let eq = rest.find('=')?;
+    let key = &rest[..eq];
+    if !valid_key(key) {
+        return None;
+    }
+
+    let (value, trailing) = unquote(&rest[eq + 1..]);
+    Some(Assignment {
+        prefix,
+        key,
+        value,
+        trailing,
+    })
+}
+
+/// Shell-unquotes first word of the value returning it with the rest of the raw value starting at unquoted white space
+fn unquote(raw: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = raw.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\'' => value.extend(chars.by_ref().map(|(_, c)| c).take_while(|c| *c != '\'')),
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next().map(|(_, c)| c) {
+                            Some(c @ '$') | Some(c @ '`') | Some(c @ '"') | Some(c @ '\\') => {
+                                value.push(c)
+                            }
+                            Some('\n') | None => (),
+                            Some(c) => {
+                                value.push('\\');
+                                value.push(c);
+                            }
+                        },
+                        c => value.push(c),
+                    }
+                }
+            }
+            '\\' => value.extend(chars.next().map(|(_, c)| c)),
+            c if c.is_whitespace() => return (value, &raw[index..]),
+            c => value.push(c),
+        }
+    }
+
+    (value, "")
+}
+
+/// Quotes value with double quotes only if it contains characters special to shell
+fn quote(value: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+
+    if !value.is_empty() && value.chars().all(safe) {
+        return value.to_owned();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if let '\\' | '"' | '$' | '`' = c {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl EnvEditor {
+    pub fn load<R: Read>(data: R) -> Result<EnvEditor, std::io::Error> {
+        Ok(EnvEditor {
+            lines: BufReader::new(data).lines().collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Indices of lines with assignment of given key
+    fn find_key(&self, key: &str) -> Vec<usize> {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| matches!(parse_assignment(line), Some(assignment) if assignment.key == key))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    fn replaced(&mut self, key: &str, value: &str) -> Result<ReplaceStatus, LinesEditorError> {
+        let index = match self.find_key(key).as_slice() {
+            [] => return Err(LinesEditorError::NotApplicable(value.to_owned())),
+            [index] => *index,
+            _ => return Err(LinesEditorError::MultipleCandidates),
+        };
+
+        let assignment = parse_assignment(&self.lines[index]).expect("assignment line");
+        if assignment.value == value {
+            return Ok(ReplaceStatus::AlreadyPresent);
+        }
+
+        let line = format!(
+            "{}{}={}{}",
+            assignment.prefix,
+            key,
+            quote(value),
+            assignment.trailing
+        );
+        self.lines[index] = line;
+
+        Ok(ReplaceStatus::Replaced)
+    }
+
+    fn present(
+        &mut self,
+        key: &str,
+        value: &str,
+        export: bool,
+        placement: &Placement,
+    ) -> Result<PresentStatus, LinesEditorError> {
+        let line = if export {
+            format!("{} {}={}", EXPORT, key, quote(value))
+        } else {
+            format!("{}={}", key, quote(value))
+        };
+
+        place_line(&mut self.lines, line, placement, |lines, anchor| {
+            single_position(lines, |line| anchor.is_match(line))
+        })
+    }
+
+    fn absent(&mut self, key: &str, value: &str) -> Result<AbsentStatus, LinesEditorError> {
+        let matching = self
+            .find_key(key)
+            .into_iter()
+            .filter(|index| {
+                matches!(parse_assignment(&self.lines[*index]), Some(assignment) if assignment.value == value)
+            })
+            .collect::<Vec<_>>();
+
+        match matching.as_slice() {
+            [] => Ok(AbsentStatus::AlreadyAbsent),
+            [index] => {
+                self.lines.remove(*index);
+                Ok(AbsentStatus::Removed)
+            }
+            _ => Err(LinesEditorError::MultipleCandidates),
+        }
+    }
+
+    pub fn edit_variable(
+        &mut self,
+        key: String,
+        value: String,
+        export: bool,
+        ensure: Ensure,
+    ) -> Result<EditStatus, LinesEditorError> {
+        if !valid_key(&key) {
+            return Err(LinesEditorError::InvalidKey(key));
+        }
+
+        let status = match ensure {
+            Ensure::Present { placement } => {
+                info!("Ensuring variable {:?} has value {:?}", key, value);
+                match self.replaced(&key, &value) {
+                    Err(LinesEditorError::NotApplicable(_)) => {
+                        self.present(&key, &value, export, &placement)?.into()
+                    }
+                    Err(err) => return Err(err),
+                    Ok(status) => status.into(),
+                }
+            }
+            Ensure::Absent => {
+                info!(
+                    "Ensuring variable {:?} with value {:?} is absent",
+                    key, value
+                );
+                self.absent(&key, &value)?.into()
+            }
+        };
+
+        debug!("Edit variable:\n{:?}:\n{:#?}", status, self);
+        Ok(status)
+    }
+}
+
+impl fmt::Display for EnvEditor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_lines(f, &self.lines)
+    }
+}
@@ -0,0 +1,809 @@
+use cotton::prelude::result::Result as PResult;
+use cotton::prelude::*;
+
+use fs2::FileExt;
+use glob::Pattern;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::ErrorKind;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+use walkdir::WalkDir;
+
+const GLOB_CHARS: &[char] = &['*', '?', '['];
+/// Number of bytes at the beginning of a file inspected to detect binary files
+const BINARY_CHECK_LEN: u64 = 8192;
+/// Prefix of temporary files created next to written file
+const TEMP_PREFIX: &str = ".pedit";
+/// Placeholder for file path in validation command
+const VALIDATE_PATH: &str = "%s";
+/// Suffix of lock file created next to locked file
+const LOCK_SUFFIX: &str = ".lock";
+/// Maximum number of symbolic links followed to find written file
+const MAX_LINKS: usize = 40;
+/// How often to retry taking a lock held by other process
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Expands glob patterns to list of matching paths; patterns without glob characters are taken as paths
+///
+/// Glob pattern that matches no files is an error as it is likely mistyped.
+pub fn expand_patterns(patterns: &[String]) -> PResult<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+
+    for pattern in patterns {
+        if !pattern.contains(GLOB_CHARS) {
+            paths.push(PathBuf::from(pattern));
+            continue;
+        }
+
+        let matches = glob::glob(pattern)
+            .problem_while_with(|| format!("parsing glob pattern {:?}", pattern))?
+            .collect::<Result<Vec<_>, _>>()
+            .problem_while_with(|| format!("listing files matching pattern {:?}", pattern))?
+            .into_iter()
+            .filter(|path| path.is_file())
+            .collect::<Vec<_>>();
+
+        if matches.is_empty() {
+            return problem!("No files matching pattern {:?}", pattern);
+        }
+        paths.extend(matches);
+    }
+
+    Ok(unique(paths))
+}
+
+/// Removes repeated paths keeping the first occurrence of each so that no file is edited twice
+pub fn unique(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    paths
+        .into_iter()
+        .filter(|path| seen.insert(path.clone()))
+        .collect()
+}
+
+/// Matches pattern against file name or, if pattern contains path separator, against path relative to walked directory
+fn matches(pattern: &Pattern, relative: &Path) -> bool {
+    if pattern.as_str().contains('/') {
+        pattern.matches_path(relative)
+    } else {
+        matches!(relative.file_name(), Some(name) if pattern.matches(&name.to_string_lossy()))
+    }
+}
+
+/// Lists files in given directory and its subdirectories that match any of include patterns (if given) and none of exclude patterns
+pub fn walk(dir: &Path, include: &[Pattern], exclude: &[Pattern]) -> PResult<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let excluded = |path: &Path| {
+        let relative = path.strip_prefix(dir).unwrap_or(path);
+        exclude.iter().any(|pattern| matches(pattern, relative))
+    };
+
+    for entry in WalkDir::new(dir)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || !excluded(entry.path()))
+    {
+        let entry =
+            entry.problem_while_with(|| format!("listing files in directory {}", dir.display()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        if is_own_file(entry.path()) {
+            debug!("Skipping lock or temporary file {}", entry.path().display());
+            continue;
+        }
+
+        let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        if !include.is_empty() && !include.iter().any(|pattern| matches(pattern, relative)) {
+            continue;
+        }
+
+        if is_binary(entry.path())
+            .problem_while_with(|| format!("reading file {}", entry.path().display()))?
+        {
+            debug!("Skipping binary file {}", entry.path().display());
+            continue;
+        }
+
+        paths.push(entry.into_path());
+    }
+
+    Ok(paths)
+}
+
+/// Checks if file is temporary file or lock file created by this tool next to edited file
+fn is_own_file(path: &Path) -> bool {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return false,
+    };
+    name.starts_with(TEMP_PREFIX)
+        || matches!(name.strip_suffix(LOCK_SUFFIX), Some(locked) if path.with_file_name(locked).is_file())
+}
+
+/// Checks if file looks like binary file by looking for NUL byte at its beginning
+fn is_binary(path: &Path) -> Result<bool, std::io::Error> {
+    let mut data = Vec::new();
+    File::open(path)?
+        .take(BINARY_CHECK_LEN)
+        .read_to_end(&mut data)?;
+    Ok(data.contains(&0))
+}
+
+/// How to create written file if it does not exist yet
+#[derive(Debug, Default, Clone)]
+pub struct NewFile {
+    /// Create missing parent directories
+    pub create_dirs: bool,
+    /// Permission bits; default permissions are subject to umask
+    pub mode: Option<u32>,
+    /// User name or id
+    pub owner: Option<String>,
+    /// Group name or id
+    pub group: Option<String>,
+}
+
+impl NewFile {
+    /// Sets permissions and ownership of file that is about to replace not existing file
+    #[cfg(unix)]
+    fn apply(&self, path: &Path) -> PResult<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Some(mode) = self.mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))
+                .problem_while("setting permissions of new file")?;
+        }
+
+        if self.owner.is_some() || self.group.is_some() {
+            let uid = self.owner.as_deref().map(user_id).transpose()?;
+            let gid = self.group.as_deref().map(group_id).transpose()?;
+            std::os::unix::fs::chown(path, uid, gid).problem_while("setting owner of new file")?;
+        }
+        Ok(())
+    }
+
+    /// Checks that owner and group of new file exist so that misspelled name fails before any file is edited
+    #[cfg(unix)]
+    pub fn check(&self) -> PResult<()> {
+        self.owner.as_deref().map(user_id).transpose()?;
+        self.group.as_deref().map(group_id).transpose()?;
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn check(&self) -> PResult<()> {
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply(&self, _path: &Path) -> PResult<()> {
+        if self.mode.is_some() || self.owner.is_some() || self.group.is_some() {
+            warn!("Setting mode and owner of new file is not supported on this platform");
+        }
+        Ok(())
+    }
+}
+
+/// Resolves user name or numeric id to user id
+#[cfg(unix)]
+fn user_id(owner: &str) -> PResult<u32> {
+    owner.parse().or_else(|_| {
+        users::get_user_by_name(owner)
+            .map(|user| user.uid())
+            .ok_or_else(|| Problem::from_error(format!("Unknown user {:?}", owner)))
+    })
+}
+
+/// Resolves group name or numeric id to group id
+#[cfg(unix)]
+fn group_id(group: &str) -> PResult<u32> {
+    group.parse().or_else(|_| {
+        users::get_group_by_name(group)
+            .map(|group| group.gid())
+            .ok_or_else(|| Problem::from_error(format!("Unknown group {:?}", group)))
+    })
+}
+
+/// Writes data to file by renaming over it a temporary file written in the same directory so that the file is never seen partially written
+///
+/// Permissions of existing file are preserved; new file is created as described by `new_file`.
+/// If validation command is given it is run on the temporary file and the file is only replaced if it succeeds.
+pub fn write_atomic(
+    path: &Path,
+    data: &[u8],
+    validate_command: Option<&str>,
+    new_file: &NewFile,
+) -> PResult<()> {
+    let mut file = AtomicFile::create(path, new_file)?;
+    file.write_all(data)
+        .problem_while("writing temporary file")?;
+    file.commit(validate_command)
+}
+
+/// Temporary file written in the directory of target file that replaces the target when committed; if it is
+/// dropped without commit the target is left untouched
+pub struct AtomicFile {
+    temp: NamedTempFile,
+    path: PathBuf,
+    new_file: NewFile,
+}
+
+impl AtomicFile {
+    /// Creates temporary file for given target path creating missing parent directories if requested
+    ///
+    /// Symbolic links are followed so that the file they point to is replaced and the link is kept.
+    pub fn create(path: &Path, new_file: &NewFile) -> PResult<AtomicFile> {
+        let path = &resolve_links(path)?;
+        let dir = match path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+        if new_file.create_dirs {
+            fs::create_dir_all(dir)
+                .problem_while_with(|| format!("creating parent directory {}", dir.display()))?;
+        }
+
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(TEMP_PREFIX);
+
+        // New files get default permissions subject to umask; permissions of existing files are copied when persisting
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if !path.exists() {
+                builder.permissions(fs::Permissions::from_mode(0o666));
+            }
+        }
+
+        let temp = builder
+            .tempfile_in(dir)
+            .problem_while_with(|| format!("creating temporary file in {}", dir.display()))?;
+
+        Ok(AtomicFile {
+            temp,
+            path: path.to_owned(),
+            new_file: new_file.clone(),
+        })
+    }
+
+    /// Flushes written data, runs validation command on it if given and renames temporary file over the target
+    pub fn commit(self, validate_command: Option<&str>) -> PResult<()> {
+        self.temp
+            .as_file()
+            .sync_all()
+            .problem_while("flushing temporary file")?;
+
+        if let Some(command) = validate_command {
+            validate(command, self.temp.path())?;
+        }
+
+        persist(self.temp, &self.path, &self.new_file)
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.temp.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.temp.flush()
+    }
+}
+
+/// Writes data to temporary file and runs validation command on it
+pub fn validate_data(data: &[u8], command: &str) -> PResult<()> {
+    let mut temp = tempfile::Builder::new()
+        .prefix(TEMP_PREFIX)
+        .tempfile()
+        .problem_while("creating temporary file")?;
+    temp.write_all(data)
+        .problem_while("writing temporary file")?;
+    temp.flush().problem_while("flushing temporary file")?;
+
+    validate(command, temp.path())
+}
+
+/// Runs validation command with shell with %s replaced with given file path; fails with command output if command was
+/// not successful
+///
+/// Path is passed in `PEDIT_FILE` environment variable that the placeholder expands to so it does not need quoting
+/// and the command may use shell quoting of its own.
+fn validate(command: &str, path: &Path) -> PResult<()> {
+    if !command.contains(VALIDATE_PATH) {
+        return problem!(
+            "Validation command {:?} does not contain {} placeholder for file path",
+            command,
+            VALIDATE_PATH
+        );
+    }
+
+    let shell = if cfg!(windows) {
+        cmd!(
+            "cmd",
+            "/C",
+            command.replace(VALIDATE_PATH, r#""%PEDIT_FILE%""#)
+        )
+    } else {
+        cmd!(
+            "sh",
+            "-c",
+            command.replace(VALIDATE_PATH, r#""$PEDIT_FILE""#)
+        )
+    };
+
+    debug!("Validating with command {:?}", command);
+    shell
+        .env("PEDIT_FILE", path)
+        .silent()
+        .problem_while("validating edited file")
+}
+
+/// Follows chain of symbolic links returning path of the file it ends with, which may not exist
+fn resolve_links(path: &Path) -> PResult<PathBuf> {
+    let mut path = path.to_owned();
+    for _ in 0..MAX_LINKS {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let target = fs::read_link(&path)
+                    .problem_while_with(|| format!("reading symbolic link {}", path.display()))?;
+                // Relative link target is relative to the directory of the link; absolute target replaces the path
+                path = match path.parent() {
+                    Some(dir) => dir.join(target),
+                    None => target,
+                };
+            }
+            _ => return Ok(path),
+        }
+    }
+    problem!("Too many levels of symbolic links at {}", path.display())
+}
+
+/// Renames temporary file over the target path copying ownership, permissions and extended attributes of the target
+/// file if it exists or setting them as described by `new_file` if it does not
+fn persist(temp: NamedTempFile, path: &Path, new_file: &NewFile) -> PResult<()> {
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            // Before permissions as changing owner may clear set-user-ID and set-group-ID bits
+            #[cfg(unix)]
+            copy_owner(&metadata, path, temp.path());
+            fs::set_permissions(temp.path(), metadata.permissions())
+                .problem_while("copying permissions to temporary file")?;
+            // After permissions as changing mode would also change ACL mask
+            #[cfg(unix)]
+            copy_xattrs(path, temp.path());
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => new_file.apply(temp.path())?,
+        Err(err) => {
+            Err(err).problem_while_with(|| format!("reading metadata of {}", path.display()))?
+        }
+    }
+
+    temp.persist(path)
+        .map_err(|err| err.error)
+        .problem_while_with(|| format!("renaming temporary file to {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Sets owner and group of a file to these of the original file; ownership that could not be preserved (e.g. when not
+/// running as root) is reported with a warning
+#[cfg(unix)]
+fn copy_owner(metadata: &fs::Metadata, from: &Path, to: &Path) {
+    use std::os::unix::fs::MetadataExt;
+
+    if let Err(err) = std::os::unix::fs::chown(to, Some(metadata.uid()), Some(metadata.gid())) {
+        warn!(
+            "Failed to preserve owner {}:{} of {}: {}",
+            metadata.uid(),
+            metadata.gid(),
+            from.display(),
+            err
+        );
+    }
+}
+
+/// Copies extended attributes, including SELinux context (`security.selinux`) and ACLs (`system.posix_acl_*`), from
+/// one file to another; attributes that could not be copied (e.g. for lack of privileges) are reported with a warning
+#[cfg(unix)]
+fn copy_xattrs(from: &Path, to: &Path) {
+    let names = match xattr::list(from) {
+        Ok(names) => names,
+        Err(err) => {
+            warn!(
+                "Failed to list extended attributes of {}; they will not be preserved: {}",
+                from.display(),
+                err
+            );
+            return;
+        }
+    };
+
+    for name in names {
+        let result = xattr::get(from, &name).and_then(|value| match value {
+            Some(value) => xattr::set(to, &name, &value),
+            None => Ok(()),
+        });
+        if let Err(err) = result {
+            warn!(
+                "Failed to preserve extended attribute {} of {}: {}",
+                name.to_string_lossy(),
+                from.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Original content of a file taken before it is written so that it can be restored
+#[derive(Debug)]
+pub struct Backup {
+    path: PathBuf,
+    /// Content of the file or `None` if it did not exist
+    data: Option<Vec<u8>>,
+}
+
+impl Backup {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes original content back or removes the file if it did not exist before
+    pub fn restore(&self) -> PResult<()> {
+        match &self.data {
+            Some(data) => write_atomic(&self.path, data, None, &NewFile::default()),
+            None => match fs::remove_file(&self.path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => {
+                    Err(err).problem_while_with(|| format!("removing file {}", self.path.display()))
+                }
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+/// Reads current content of a file so it can be restored later
+pub fn backup(path: &Path) -> PResult<Backup> {
+    let data = match fs::read(path) {
+        Ok(data) => Some(data),
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => Err(err).problem_while_with(|| format!("reading file {}", path.display()))?,
+    };
+
+    Ok(Backup {
+        path: path.to_owned(),
+        data,
+    })
+}
+
+/// Exclusive advisory lock on a file released when dropped
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+/// Takes exclusive lock on lock file placed next to given file waiting up to given timeout for other lock holder to release it
+///
+/// Lock file is used instead of the file itself as the file gets replaced by atomic write. It is left in place so that
+/// all processes lock the same file. Symbolic links are followed so that edits through different links to the same file
+/// take the same lock.
+pub fn lock(path: &Path, timeout: Duration) -> PResult<FileLock> {
+    let path = resolve_links(path)?;
+    let mut lock_path = OsString::from(path.as_os_str());
+    lock_path.push(LOCK_SUFFIX);
+    let lock_path = PathBuf::from(lock_path);
+
+    if let Some(dir) = lock_path.parent().filter(|dir| *dir != Path::new("")) {
+        fs::create_dir_all(dir)
+            .problem_while_with(|| format!("creating parent directory {}", dir.display()))?;
+    }
+
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)
+        .problem_while_with(|| format!("opening lock file {}", lock_path.display()))?;
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        match file.try_lock_exclusive() {
+            Ok(()) => {
+                debug!("Locked {}", lock_path.display());
+                return Ok(FileLock { file });
+            }
+            Err(err) if err.kind() == fs2::lock_contended_error().kind() => {
+                if Instant::now() >= deadline {
+                    return problem!(
+                        "Failed to acquire lock {} within {} seconds; other process is editing the file",
+                        lock_path.display(),
+                        timeout.as_secs()
+                    );
+                }
+                sleep(LOCK_RETRY_INTERVAL);
+            }
+            Err(err) => {
+                return Err(err)
+                    .problem_while_with(|| format!("locking file {}", lock_path.display()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_dirs() -> NewFile {
+        NewFile {
+            create_dirs: true,
+            ..Default::default()
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_xattrs() -> FinalResult {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("hosts");
+        fs::write(&file, "127.0.0.1 localhost\n")?;
+
+        // Not all file systems support user extended attributes
+        if xattr::set(&file, "user.pedit", b"test").is_err() {
+            return Ok(());
+        }
+
+        write_atomic(&file, b"10.0.0.1 db\n", None, &with_dirs())?;
+        let value = xattr::get(&file, "user.pedit")?;
+
+        assert_eq!(value.as_deref(), Some(&b"test"[..]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_patterns() -> FinalResult {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir_all(dir.path().join("sub"))?;
+        for file in &["a.conf", "b.conf", "c.txt", "sub/d.conf"] {
+            File::create(dir.path().join(file))?;
+        }
+
+        let paths = expand_patterns(&[
+            format!("{}/*.conf", dir.path().display()),
+            format!("{}/missing.conf", dir.path().display()),
+            format!("{}/a.conf", dir.path().display()),
+            format!("{}/?.conf", dir.path().display()),
+        ])?;
+        let err = expand_patterns(&[format!("{}/*.cnf", dir.path().display())]).unwrap_err();
+
+        assert!(err.to_string().starts_with("No files matching pattern"));
+        assert_eq!(
+            paths,
+            vec![
+                dir.path().join("a.conf"),
+                dir.path().join("b.conf"),
+                dir.path().join("missing.conf")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk() -> FinalResult {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir_all(dir.path().join("app/.git"))?;
+        fs::create_dir_all(dir.path().join("lib"))?;
+        for file in &[
+            "app/application.properties",
+            "app/.git/application.properties",
+            "lib/application.properties",
+            "lib/other.properties",
+        ] {
+            fs::write(dir.path().join(file), "foo=bar\n")?;
+        }
+        fs::write(dir.path().join("app/binary.properties"), b"foo\0bar")?;
+        fs::write(dir.path().join("app/application.properties.lock"), "")?;
+        fs::write(dir.path().join("app/.pedit1234.properties"), "")?;
+        fs::write(dir.path().join("app/other.lock"), "")?;
+
+        let paths = walk(
+            dir.path(),
+            &[Pattern::new("*.properties*")?, Pattern::new("*.lock")?],
+            &[Pattern::new(".git")?, Pattern::new("lib/other.*")?],
+        )?;
+
+        assert_eq!(
+            paths,
+            vec![
+                dir.path().join("app/application.properties"),
+                dir.path().join("app/other.lock"),
+                dir.path().join("lib/application.properties")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_atomic() -> FinalResult {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("staging/app.conf");
+
+        write_atomic(&file, b"foo\n", None, &with_dirs())?;
+        assert_eq!(fs::read_to_string(&file)?, "foo\n");
+
+        let mut permissions = fs::metadata(&file)?.permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&file, permissions)?;
+
+        write_atomic(&file, b"bar\n", None, &with_dirs())?;
+        assert_eq!(fs::read_to_string(&file)?, "bar\n");
+        assert!(fs::metadata(&file)?.permissions().readonly());
+        assert_eq!(fs::read_dir(dir.path().join("staging"))?.count(), 1);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_new_file() -> FinalResult {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("conf.d/app.conf");
+
+        let err = write_atomic(&file, b"foo\n", None, &NewFile::default()).unwrap_err();
+        assert!(err.to_string().starts_with("while creating temporary file"));
+
+        let new_file = NewFile {
+            create_dirs: true,
+            mode: Some(0o640),
+            owner: Some(users::get_current_uid().to_string()),
+            group: Some(users::get_current_gid().to_string()),
+        };
+        write_atomic(&file, b"foo\n", None, &new_file)?;
+        assert_eq!(fs::metadata(&file)?.permissions().mode() & 0o7777, 0o640);
+
+        // Existing file keeps its permissions
+        let new_file = NewFile {
+            mode: Some(0o600),
+            ..new_file
+        };
+        write_atomic(&file, b"bar\n", None, &new_file)?;
+        assert_eq!(fs::metadata(&file)?.permissions().mode() & 0o7777, 0o640);
+
+        let new_file = NewFile {
+            owner: Some("no-such-user-pedit".to_owned()),
+            ..Default::default()
+        };
+        let err =
+            write_atomic(&dir.path().join("other.conf"), b"foo\n", None, &new_file).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with(r#"Unknown user "no-such-user-pedit""#));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_symlink() -> FinalResult {
+        let dir = tempfile::tempdir()?;
+        let real = dir.path().join("real.conf");
+        let link = dir.path().join("link.conf");
+        fs::write(&real, "foo\n")?;
+        std::os::unix::fs::symlink("real.conf", &link)?;
+
+        write_atomic(&link, b"bar\n", None, &NewFile::default())?;
+        let is_symlink = fs::symlink_metadata(&link)?.file_type().is_symlink();
+        let content = fs::read_to_string(&real)?;
+
+        assert!(is_symlink);
+        assert_eq!(content, "bar\n");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_owner() -> FinalResult {
+        use std::os::unix::fs::MetadataExt;
+
+        // Changing owner requires root
+        if users::get_current_uid() != 0 {
+            return Ok(());
+        }
+
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("app.conf");
+        fs::write(&file, "foo\n")?;
+        std::os::unix::fs::chown(&file, Some(65534), Some(65534))?;
+
+        write_atomic(&file, b"bar\n", None, &NewFile::default())?;
+        let metadata = fs::metadata(&file)?;
+
+        assert_eq!((metadata.uid(), metadata.gid()), (65534, 65534));
+        Ok(())
+    }
+
+    #[test]
+    fn test_backup_restore() -> FinalResult {
+        let dir = tempfile::tempdir()?;
+        let existing = dir.path().join("sshd_config");
+        let created = dir.path().join("sshd_config.d/pedit.conf");
+
+        write_atomic(&existing, b"PermitRootLogin yes\n", None, &with_dirs())?;
+        let backups = vec![backup(&existing)?, backup(&created)?];
+        write_atomic(&existing, b"PermitRootLogin no\n", None, &with_dirs())?;
+        write_atomic(&created, b"PasswordAuthentication no\n", None, &with_dirs())?;
+
+        for backup in &backups {
+            backup.restore()?;
+        }
+        assert_eq!(fs::read_to_string(&existing)?, "PermitRootLogin yes\n");
+        assert!(!created.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_timeout() -> FinalResult {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("hosts");
+
+        let lock1 = lock(&file, Duration::from_secs(1))?;
+        let err = lock(&file, Duration::from_millis(100)).unwrap_err();
+        assert!(err.to_string().starts_with("Failed to acquire lock"));
+
+        drop(lock1);
+        lock(&file, Duration::from_millis(100))?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lock_symlinks() -> FinalResult {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("hosts");
+        let link1 = dir.path().join("link1");
+        let link2 = dir.path().join("link2");
+        fs::write(&file, "")?;
+        std::os::unix::fs::symlink("hosts", &link1)?;
+        std::os::unix::fs::symlink(&file, &link2)?;
+
+        let _lock = lock(&link1, Duration::from_secs(1))?;
+        assert!(lock(&link2, Duration::from_millis(100)).is_err());
+        assert!(dir.path().join("hosts.lock").exists());
+        assert!(!dir.path().join("link1.lock").exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_validate() -> FinalResult {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("sshd_config");
+        write_atomic(&file, b"foo\n", None, &with_dirs())?;
+
+        let err = write_atomic(&file, b"bar\n", Some("grep -q foo %s"), &with_dirs()).unwrap_err();
+        assert!(err.to_string().starts_with("while validating edited file"));
+        assert_eq!(fs::read_to_string(&file)?, "foo\n");
+        assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+
+        write_atomic(&file, b"foo bar\n", Some("grep -q foo %s"), &with_dirs())?;
+        assert_eq!(fs::read_to_string(&file)?, "foo bar\n");
+
+        write_atomic(
+            &file,
+            b"foo baz\n",
+            Some("sh -c 'grep -q \"foo baz\" %s'"),
+            &with_dirs(),
+        )?;
+        assert_eq!(fs::read_to_string(&file)?, "foo baz\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,414 @@
+use crate::editor::*;
+use crate::lines_editor::{write_lines, LinesEditorError};
+
+use cotton::prelude::*;
+use std::ops::Range;
+
+const DEFAULT_INDENT: &str = "\t";
+
+/// Editor for git-config style INI files with subsections, case-insensitive names and multi-valued keys
+#[derive(Debug)]
+pub struct GitConfigEditor {
+    lines: Vec<String>,
+}
+
+/// Fully qualified key in form of `section.key` or `section.subsection.key`
+#[derive(Debug)]
+struct ConfigKey {
+    section: String,
+    subsection: Option<String>,
+    /// Name as given; names are compared case-insensitively
+    name: String,
+}
+
+impl ConfigKey {
+    fn parse(key: &str) -> Result<ConfigKey, LinesEditorError> {
+        let invalid = || LinesEditorError::InvalidKey(key.to_owned());
+
+        let (section, rest) = key.split_at(key.find('.').ok_or_else(invalid)?);
+        let rest = &rest[1..];
+        let (subsection, name) = match rest.rfind('.') {
+            Some(pos) => (Some(&rest[..pos]), &rest[pos + 1..]),
+            None => (None, rest),
+        };
+
+        let valid_name = |name: &str, extra: &[char]| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || extra.contains(&c))
+        };
+
+        if !valid_name(section, &['.']) || !valid_name(name, &[]) || name.starts_with('-') {
+            return Err(invalid());
+        }
+
+        Ok(ConfigKey {
+            section: section.to_ascii_lowercase(),
+            subsection: subsection.map(ToOwned::to_owned),
+            name: name.to_owned(),
+        })
+    }
+
+    fn header(&self) -> String {
+        match &self.subsection {
+            Some(subsection) => format!(
+                r#"[{} "{}"]"#,
+                self.section,
+                subsection.replace('\\', r"\\").replace('"', r#"\""#)
+            ),
+            None => format!("[{}]", self.section),
+        }
+    }
+
+    fn entry(&self, indent: &str, value: &str) -> String {
+        entry(indent, &self.name, value)
+    }
+}
+
+fn entry(indent: &str, name: &str, value: &str) -> String {
+    format!("{}{} = {}", indent, name, quote_value(value))
+}
+
+/// Splits line to section header and the rest that may hold an entry; entry can follow the header on the same line
+fn split_header(line: &str) -> (&str, &str) {
+    match parse_header(line) {
+        Some(_) => line.split_at(line.find(']').unwrap() + 1),
+        None => ("", line),
+    }
+}
+
+/// Parses section header line returning lower-cased section name and subsection if present
+fn parse_header(line: &str) -> Option<(String, Option<String>)> {
+    let line = line.trim_start();
+    if !line.starts_with('[') {
+        return None;
+    }
+    let header = &line[1..line.find(']')?];
+
+    if let Some(quote) = header.find('"') {
+        let section = header[..quote].trim();
+        let mut subsection = String::new();
+        let mut chars = header[quote + 1..].chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => subsection.extend(chars.next()),
+                '"' => break,
+                c => subsection.push(c),
+            }
+        }
+        Some((section.to_ascii_lowercase(), Some(subsection)))
+    } else {
+        // Deprecated `[section.subsection]` syntax has case-insensitive subsection
+        let header = header.trim().to_ascii_lowercase();
+        match header.find('.') {
+            Some(pos) => Some((header[..pos].to_owned(), Some(header[pos + 1..].to_owned()))),
+            None => Some((header, None)),
+        }
+    }
+}
+
+/// Parses key line returning key name as written and its unquoted value
+fn parse_entry(line: &str) -> Option<(String, String)> {
+    let line = line.trim_start();
+    let name_len = line
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+        .unwrap_or(line.len());
+    if name_len == 0 {
+        return None;
+    }
+
+    let (name, rest) = line.split_at(name_len);
+    let rest = rest.trim_start();
+    let value = if rest.is_empty() || rest.starts_with('#') || rest.starts_with(';') {
+        // Key with no value is boolean true
+        "true".to_owned()
+    } else if let Some(raw) = rest.strip_prefix('=') {
+        unquote_value(raw)
+    } else {
+        return None;
+    };
+
+    Some((name.to_owned(), value))
+}
+
+fn unquote_value(raw: &str) -> String {
+    let mut value = String::new();
+    // Length of value not counting trailing unquoted white space
+    let mut value_len = 0;
+    let mut quoted = false;
+    let mut chars = raw.trim_start().chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('b') => {
+                    value.pop();
+                }
+                Some(c) => value.push(c),
+                None => (),
+            },
+            '#' | ';' if !quoted => break,
+            c if c.is_whitespace() && !quoted => {
+                value.push(c);
+                continue;
+            }
+            c => value.push(c),
+        }
+        value_len = value.len();
+    }
+
+    value.truncate(value_len);
+    value
+}
+
+fn quote_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+        .replace('\t', r"\t");
+
+    if value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';'])
+    {
+        format!(r#""{}""#, escaped)
+    } else {
+        escaped
+    }
+}
+
+impl GitConfigEditor {
+    pub fn load<R: Read>(data: R) -> Result<GitConfigEditor, std::io::Error> {
+        Ok(GitConfigEditor {
+            lines: BufReader::new(data).lines().collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Line ranges of bodies (excluding header) of all sections matching given key
+    fn sections(&self, key: &ConfigKey) -> Vec<Range<usize>> {
+        let mut sections = Vec::new();
+        let mut current: Option<usize> = None;
+
+        for (index, line) in self.lines.iter().enumerate() {
+            if let Some((section, subsection)) = parse_header(line) {
+                if let Some(start) = current.take() {
+                    sections.push(start..index);
+                }
+                if section == key.section && subsection == key.subsection {
+                    current = Some(index + 1);
+                }
+            }
+        }
+        if let Some(start) = current {
+            sections.push(start..self.lines.len());
+        }
+
+        sections
+    }
+
+    /// Line indices and values of all entries of given key including entries on section header lines
+    fn entries(&self, key: &ConfigKey) -> Vec<(usize, String)> {
+        self.sections(key)
+            .into_iter()
+            .flat_map(|section| section.start - 1..section.end)
+            .filter_map(|index| {
+                parse_entry(split_header(&self.lines[index]).1)
+                    .filter(|(name, _)| name.eq_ignore_ascii_case(&key.name))
+                    .map(|(_, value)| (index, value))
+            })
+            .collect()
+    }
+
+    fn indent(&self, section: &Range<usize>) -> String {
+        self.lines[section.clone()]
+            .iter()
+            .find(|line| parse_entry(line).is_some())
+            .map(|line| line[..line.len() - line.trim_start().len()].to_owned())
+            .unwrap_or_else(|| DEFAULT_INDENT.to_owned())
+    }
+
+    fn replaced(
+        &mut self,
+        key: &ConfigKey,
+        value: &str,
+    ) -> Result<ReplaceStatus, LinesEditorError> {
+        let entries = self.entries(key);
+        match entries.as_slice() {
+            [] => Err(LinesEditorError::NotApplicable(value.to_owned())),
+            [(_, current)] if current == value => Ok(ReplaceStatus::AlreadyPresent),
+            [(index, _)] => {
+                // Keep the key spelled as it is in the file
+                let (header, line) = split_header(&self.lines[*index]);
+                let indent = &line[..line.len() - line.trim_start().len()];
+                let (name, _) = parse_entry(line).expect("entry line");
+                self.lines[*index] = format!("{}{}", header, entry(indent, &name, value));
+                Ok(ReplaceStatus::Replaced)
+            }
+            _ => Err(LinesEditorError::MultipleCandidates),
+        }
+    }
+
+    fn present(
+        &mut self,
+        key: &ConfigKey,
+        value: &str,
+        placement: &Placement,
+    ) -> Result<PresentStatus, LinesEditorError> {
+        if self
+            .entries(key)
+            .iter()
+            .any(|(_, current)| current == value)
+        {
+            return Ok(PresentStatus::AlreadyPresent);
+        }
+
+        let sections = self.sections(key);
+
+        if sections.is_empty() {
+            let new_section = vec![key.header(), key.entry(DEFAULT_INDENT, value)];
+            match placement {
+                Placement::AtTop => {
+                    self.lines.splice(0..0, new_section);
+                }
+                Placement::AtEnd => self.lines.extend(new_section),
+                Placement::RelativeTo { .. } => {
+                    return Err(LinesEditorError::NotApplicable(value.to_owned()))
+                }
+            }
+            return Ok(PresentStatus::InsertedPlacement);
+        }
+
+        let (position, section) = match placement {
+            Placement::AtTop => (sections[0].start, &sections[0]),
+            Placement::AtEnd => {
+                let section = sections.last().unwrap();
+                // Keep blank lines separating sections after the new entry
+                let position = self.lines[section.clone()]
+                    .iter()
+                    .rposition(|line| !line.trim().is_empty())
+                    .map(|last| section.start + last + 1)
+                    .unwrap_or(section.start);
+                (position, section)
+            }
+            Placement::RelativeTo { anchor, relation } => {
+                let mut iter = sections.iter().flat_map(|section| {
+                    section
+                        .clone()
+                        .filter(|index| anchor.is_match(&self.lines[*index]))
+                        .map(move |index| (index, section))
+                });
+                if let Some((index, section)) = iter.next() {
+                    if iter.next().is_some() {
+                        return Err(LinesEditorError::MultipleCandidates);
+                    }
+
+                    match relation {
+                        AnchorRelation::Before => (index, section),
+                        AnchorRelation::After => (index + 1, section),
+                    }
+                } else {
+                    return Err(LinesEditorError::NotApplicable(value.to_owned()));
+                }
+            }
+        };
+
+        let entry = key.entry(&self.indent(section), value);
+        self.lines.insert(position, entry);
+
+        Ok(PresentStatus::InsertedPlacement)
+    }
+
+    fn absent(
+        &mut self,
+        key: &ConfigKey,
+        value: &str,
+        multikey: bool,
+    ) -> Result<AbsentStatus, LinesEditorError> {
+        let matching = self
+            .entries(key)
+            .into_iter()
+            .filter(|(_, current)| current == value)
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+
+        if matching.is_empty() {
+            return Ok(AbsentStatus::AlreadyAbsent);
+        }
+        if matching.len() > 1 && !multikey {
+            return Err(LinesEditorError::MultipleCandidates);
+        }
+
+        for index in matching.into_iter().rev() {
+            match split_header(&self.lines[index]) {
+                ("", _) => {
+                    self.lines.remove(index);
+                }
+                (header, _) => self.lines[index] = header.to_owned(),
+            }
+        }
+        self.remove_empty_sections(key);
+
+        Ok(AbsentStatus::Removed)
+    }
+
+    /// Removes headers of sections for given key that have only blank lines left in them
+    fn remove_empty_sections(&mut self, key: &ConfigKey) {
+        for section in self.sections(key).into_iter().rev() {
+            let (_, header_entry) = split_header(&self.lines[section.start - 1]);
+            if header_entry.trim().is_empty()
+                && self.lines[section.clone()]
+                    .iter()
+                    .all(|line| line.trim().is_empty())
+            {
+                self.lines.drain(section.start - 1..section.end);
+            }
+        }
+    }
+
+    pub fn edit_key(
+        &mut self,
+        key: String,
+        value: String,
+        multikey: bool,
+        ensure: Ensure,
+    ) -> Result<EditStatus, LinesEditorError> {
+        let config_key = ConfigKey::parse(&key)?;
+
+        let status = match ensure {
+            Ensure::Present { placement } => {
+                info!("Ensuring key {:?} has value {:?}", key, value);
+                let replaced = if multikey {
+                    // Values of multi-valued keys are never replaced, only added
+                    Err(LinesEditorError::NotApplicable(value))
+                } else {
+                    self.replaced(&config_key, &value)
+                };
+                match replaced {
+                    Err(LinesEditorError::NotApplicable(value)) => {
+                        self.present(&config_key, &value, &placement)?.into()
+                    }
+                    Err(err) => return Err(err),
+                    Ok(status) => status.into(),
+                }
+            }
+            Ensure::Absent => {
+                info!("Ensuring key {:?} with value {:?} is absent", key, value);
+                self.absent(&config_key, &value, multikey)?.into()
+            }
+        };
+
+        debug!("Edit key:\n{:?}:\n{:#?}", status, self);
+        Ok(status)
+    }
+}
+
+impl fmt::Display for GitConfigEditor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_lines(f, &self.lines)
+    }
+}
@@ -1,21 +1,34 @@
 use crate::editor::*;
 
 use cotton::prelude::*;
-use regex::Regex;
+use regex::{Regex, RegexSet};
+use std::borrow::Cow;
 use std::error::Error;
 
 const NEW_LINE: &str = "\n";
+const CONTINUATION: &str = "\\\n";
 
 #[derive(Debug)]
 pub struct LinesEditor {
+    /// Lines of text; in continuation mode a logical line holds all its physical lines joined with new line character
     lines: Vec<String>,
 }
 
+/// Key and value pair found in a line
+#[derive(Debug)]
+pub struct Pair {
+    /// Number of (first physical) line starting from 1
+    pub line: usize,
+    pub key: String,
+    pub value: String,
+}
+
 #[derive(Debug)]
 pub enum LinesEditorError {
     InvalidPairOrSeparator,
     MultipleCandidates,
     NotApplicable(String),
+    InvalidKey(String),
 }
 
 impl fmt::Display for LinesEditorError {
@@ -27,12 +40,370 @@ impl fmt::Display for LinesEditorError {
             ),
             LinesEditorError::MultipleCandidates => write!(f, "Multiple candidates found"),
             LinesEditorError::NotApplicable(_) => write!(f, "Edit was not applicable"),
+            LinesEditorError::InvalidKey(key) => write!(f, "Invalid key: {:?}", key),
         }
     }
 }
 
 impl Error for LinesEditorError {}
 
+/// Checks if line ends with line continuation character that is not escaped
+pub fn ends_with_continuation(line: &str) -> bool {
+    line.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1
+}
+
+/// Logical line content used for matching with line continuations removed
+fn logical(line: &str) -> Cow<'_, str> {
+    if line.contains(CONTINUATION) {
+        Cow::Owned(line.replace(CONTINUATION, ""))
+    } else {
+        Cow::Borrowed(line)
+    }
+}
+
+fn is_match(pattern: &Regex, line: &str) -> bool {
+    pattern.is_match(&logical(line))
+}
+
+/// Position of the only line for which `matches` is true; it is an error if there are more such lines
+pub fn single_position(
+    lines: &[String],
+    matches: impl Fn(&str) -> bool,
+) -> Result<Option<usize>, LinesEditorError> {
+    let mut iter = lines.iter();
+    match iter.position(|line| matches(line)) {
+        Some(_) if iter.any(|line| matches(line)) => Err(LinesEditorError::MultipleCandidates),
+        position => Ok(position),
+    }
+}
+
+/// Inserts new line at given placement; `anchor_position` looks up position of the only line matching the anchor
+pub fn place_line(
+    lines: &mut Vec<String>,
+    line: String,
+    placement: &Placement,
+    anchor_position: impl FnOnce(&[String], &Regex) -> Result<Option<usize>, LinesEditorError>,
+) -> Result<PresentStatus, LinesEditorError> {
+    match placement {
+        Placement::AtTop => {
+            lines.insert(0, line);
+        }
+        Placement::AtEnd => {
+            lines.push(line);
+        }
+        Placement::RelativeTo { anchor, relation } => {
+            if let Some(position) = anchor_position(lines, anchor)? {
+                match relation {
+                    AnchorRelation::Before => lines.insert(position, line),
+                    AnchorRelation::After => lines.insert(position + 1, line),
+                }
+            } else {
+                return Err(LinesEditorError::NotApplicable(line));
+            }
+        }
+    }
+
+    Ok(PresentStatus::InsertedPlacement)
+}
+
+/// Writes lines each terminated with new line
+pub fn write_lines(f: &mut fmt::Formatter<'_>, lines: &[String]) -> fmt::Result {
+    for line in lines {
+        f.write_str(line)?;
+        f.write_str(NEW_LINE)?;
+    }
+    Ok(())
+}
+
+/// Splits key and value pair on first match of separator
+pub fn split_pair<'p>(
+    pair: &'p str,
+    separator: &Regex,
+) -> Result<(&'p str, &'p str), LinesEditorError> {
+    separator
+        .splitn(pair, 2)
+        .collect_tuple()
+        .ok_or(LinesEditorError::InvalidPairOrSeparator)
+}
+
+/// Pattern matching line consisting of given value
+pub fn line_pattern(value: &str, ignore_whitespace: bool) -> Regex {
+    Regex::new(&if ignore_whitespace {
+        format!(r#"^\s*{}\s*$"#, &regex::escape(value))
+    } else {
+        format!(r#"^{}$"#, &regex::escape(value))
+    })
+    .expect("failed to construct line_pattern regex")
+}
+
+/// Pattern matching line consisting of given key and value pair
+pub fn pair_pattern(key: &str, value: &str, ignore_whitespace: bool, separator: &Regex) -> Regex {
+    Regex::new(&if ignore_whitespace {
+        format!(
+            r#"^\s*{}{}{}\s*$"#,
+            regex::escape(key),
+            separator,
+            regex::escape(value)
+        )
+    } else {
+        format!(
+            r#"^{}{}{}$"#,
+            regex::escape(key),
+            separator,
+            regex::escape(value)
+        )
+    })
+    .expect("failed to construct pair_pattern regex")
+}
+
+/// Pattern matching beginning of line with given key up to and including the separator
+fn key_pattern(key: &str, ignore_whitespace: bool, separator: &Regex) -> Regex {
+    Regex::new(&if ignore_whitespace {
+        format!(r#"^\s*{}{}"#, regex::escape(key), separator)
+    } else {
+        format!(r#"^{}{}"#, regex::escape(key), separator)
+    })
+    .expect("failed to construct key_pattern regex")
+}
+
+/// Checks if any (logical) line of the input matches given pattern reading input line by line
+pub fn contains_match<R: Read>(
+    data: R,
+    pattern: &Regex,
+    continuation: bool,
+) -> Result<bool, std::io::Error> {
+    let mut logical = String::new();
+
+    for line in BufReader::new(data).lines() {
+        let line = line?;
+        if continuation && ends_with_continuation(&line) {
+            logical.push_str(&line);
+            logical.push_str(NEW_LINE);
+            continue;
+        }
+        logical.push_str(&line);
+
+        if is_match(pattern, &logical) {
+            return Ok(true);
+        }
+        logical.clear();
+    }
+
+    // Input ending with continued line
+    Ok(!logical.is_empty() && is_match(pattern, logical.trim_end_matches(NEW_LINE)))
+}
+
+/// Edit of a single line that can be applied while reading input line by line so that memory use does not depend on
+/// the size of the input; only edits that do not need to look back at already written lines can be streamed
+#[derive(Debug)]
+pub enum StreamEdit {
+    /// Replace the only line matching `replace_pattern` with `value` unless it already matches `value_pattern`, or
+    /// append `value` at the end if no line matches; without `replace_pattern` any line matching `value_pattern` is
+    /// left as is
+    Present {
+        value_pattern: Regex,
+        replace_pattern: Option<Regex>,
+        value: String,
+    },
+    /// Remove the only line matching pattern
+    Absent { pattern: Regex },
+}
+
+impl StreamEdit {
+    /// Streaming counterpart of [`LinesEditor::edit_line`] if placement allows it
+    pub fn line(value: &str, ignore_whitespace: bool, ensure: &Ensure) -> Option<StreamEdit> {
+        let value_pattern = line_pattern(value, ignore_whitespace);
+        match ensure {
+            Ensure::Present {
+                placement: Placement::AtEnd,
+            } => Some(StreamEdit::Present {
+                value_pattern,
+                replace_pattern: None,
+                value: value.to_owned(),
+            }),
+            Ensure::Present { .. } => None,
+            Ensure::Absent => Some(StreamEdit::Absent {
+                pattern: value_pattern,
+            }),
+        }
+    }
+
+    /// Streaming counterpart of [`LinesEditor::edit_pair`] if placement allows it
+    pub fn pair(
+        pair: &str,
+        multikey: bool,
+        ignore_whitespace: bool,
+        separator: &Regex,
+        ensure: &Ensure,
+    ) -> Result<Option<StreamEdit>, LinesEditorError> {
+        let (key, value) = split_pair(pair, separator)?;
+        let pair_pattern = pair_pattern(key, value, ignore_whitespace, separator);
+
+        Ok(match ensure {
+            Ensure::Present {
+                placement: Placement::AtEnd,
+            } => Some(StreamEdit::Present {
+                replace_pattern: Some(if multikey {
+                    pair_pattern.clone()
+                } else {
+                    key_pattern(key, ignore_whitespace, separator)
+                }),
+                value_pattern: pair_pattern,
+                value: pair.to_owned(),
+            }),
+            Ensure::Present { .. } => None,
+            Ensure::Absent => Some(StreamEdit::Absent {
+                pattern: pair_pattern,
+            }),
+        })
+    }
+
+    /// Writes (logical) line to output as edited; status is set once the line the edit applies to is found
+    fn line_to<W: Write>(
+        &self,
+        line: &str,
+        status: &mut Option<EditStatus>,
+        output: &mut W,
+    ) -> Result<Result<(), LinesEditorError>, std::io::Error> {
+        match self {
+            StreamEdit::Present {
+                value_pattern,
+                replace_pattern: Some(replace_pattern),
+                value,
+            } if is_match(replace_pattern, line) => {
+                if status.is_some() {
+                    return Ok(Err(LinesEditorError::MultipleCandidates));
+                }
+                if !is_match(value_pattern, line) {
+                    *status = Some(ReplaceStatus::Replaced.into());
+                    return writeln!(output, "{}", value).map(Ok);
+                }
+                *status = Some(ReplaceStatus::AlreadyPresent.into());
+            }
+            StreamEdit::Present {
+                value_pattern,
+                replace_pattern: None,
+                ..
+            } if is_match(value_pattern, line) => {
+                *status = Some(PresentStatus::AlreadyPresent.into());
+            }
+            StreamEdit::Absent { pattern } if is_match(pattern, line) => {
+                if status.is_some() {
+                    return Ok(Err(LinesEditorError::MultipleCandidates));
+                }
+                *status = Some(AbsentStatus::Removed.into());
+                return Ok(Ok(()));
+            }
+            _ => (),
+        }
+        writeln!(output, "{}", line).map(Ok)
+    }
+
+    /// Applies edit reading input line by line and writing edited lines to output as they are read
+    ///
+    /// Output written before an edit error is found (e.g. second candidate line) is not valid and has to be discarded.
+    pub fn apply<R: Read, W: Write>(
+        &self,
+        input: R,
+        output: W,
+        continuation: bool,
+    ) -> Result<Result<EditStatus, LinesEditorError>, std::io::Error> {
+        let mut output = BufWriter::new(output);
+        let mut status = None;
+        let mut logical = String::new();
+        let mut continued = false;
+
+        for line in BufReader::new(input).lines() {
+            let line = line?;
+            if continued {
+                logical.push_str(NEW_LINE);
+            }
+            logical.push_str(&line);
+
+            continued = continuation && ends_with_continuation(&line);
+            if !continued {
+                if let Err(err) = self.line_to(&logical, &mut status, &mut output)? {
+                    return Ok(Err(err));
+                }
+                logical.clear();
+            }
+        }
+
+        // Input ending with continued line
+        if continued {
+            if let Err(err) = self.line_to(&logical, &mut status, &mut output)? {
+                return Ok(Err(err));
+            }
+        }
+
+        let status = match (self, status) {
+            (_, Some(status)) => status,
+            (StreamEdit::Present { value, .. }, None) => {
+                writeln!(output, "{}", value)?;
+                PresentStatus::InsertedPlacement.into()
+            }
+            (StreamEdit::Absent { .. }, None) => AbsentStatus::AlreadyAbsent.into(),
+        };
+
+        output.flush()?;
+        Ok(Ok(status))
+    }
+}
+
+/// Positions of lines matching each pattern of an edit found by matching every (logical) line against all the
+/// patterns at once; index is only valid until lines are modified
+#[derive(Debug)]
+struct LineIndex {
+    patterns: Vec<String>,
+    /// Ascending positions of lines matching pattern at the same index in `patterns`
+    matches: Vec<Vec<usize>>,
+}
+
+impl LineIndex {
+    fn new<'r>(lines: &[String], patterns: impl IntoIterator<Item = &'r Regex>) -> LineIndex {
+        let patterns = patterns
+            .into_iter()
+            .map(|pattern| pattern.as_str().to_owned())
+            .collect::<Vec<_>>();
+        let set = RegexSet::new(&patterns).expect("failed to construct RegexSet of valid patterns");
+
+        let mut matches = vec![Vec::new(); patterns.len()];
+        for (position, line) in lines.iter().enumerate() {
+            for pattern in set.matches(&logical(line)).iter() {
+                matches[pattern].push(position);
+            }
+        }
+
+        LineIndex { patterns, matches }
+    }
+
+    fn positions(&self, pattern: &Regex) -> &[usize] {
+        let index = self
+            .patterns
+            .iter()
+            .position(|indexed| indexed == pattern.as_str())
+            .expect("pattern was not indexed");
+        &self.matches[index]
+    }
+
+    fn any(&self, pattern: &Regex) -> bool {
+        !self.positions(pattern).is_empty()
+    }
+
+    fn matches_at(&self, pattern: &Regex, position: usize) -> bool {
+        self.positions(pattern).binary_search(&position).is_ok()
+    }
+
+    /// Position of the only line matching pattern; it is an error if there are more such lines
+    fn single(&self, pattern: &Regex) -> Result<Option<usize>, LinesEditorError> {
+        match self.positions(pattern) {
+            [] => Ok(None),
+            [position] => Ok(Some(*position)),
+            _ => Err(LinesEditorError::MultipleCandidates),
+        }
+    }
+}
+
 impl LinesEditor {
     pub fn load<R: Read>(data: R) -> Result<LinesEditor, std::io::Error> {
         Ok(LinesEditor {
@@ -40,74 +411,80 @@ impl LinesEditor {
         })
     }
 
+    /// Loads lines joining physical lines ending with backslash with following line into single logical line
+    pub fn load_continued<R: Read>(data: R) -> Result<LinesEditor, std::io::Error> {
+        let mut lines: Vec<String> = Vec::new();
+        let mut continued = false;
+
+        for line in BufReader::new(data).lines() {
+            let line = line?;
+            let continues = ends_with_continuation(&line);
+
+            match lines.last_mut() {
+                Some(logical) if continued => {
+                    logical.push_str(NEW_LINE);
+                    logical.push_str(&line);
+                }
+                _ => lines.push(line),
+            }
+
+            continued = continues;
+        }
+
+        Ok(LinesEditor { lines })
+    }
+
+    /// Builds index of lines matching given patterns and the anchor of placement if there is one
+    fn index(&self, patterns: &[&Regex], placement: Option<&Placement>) -> LineIndex {
+        let anchor = match placement {
+            Some(Placement::RelativeTo { anchor, .. }) => Some(anchor),
+            _ => None,
+        };
+        LineIndex::new(&self.lines, patterns.iter().copied().chain(anchor))
+    }
+
     fn replaced(
         &mut self,
+        index: &LineIndex,
         pair_pattern: &Regex,
         key_pattern: &Regex,
         value: String,
     ) -> Result<ReplaceStatus, LinesEditorError> {
-        let mut iter = self.lines.iter_mut();
-        if let Some(line) = (&mut iter).find(|line| key_pattern.is_match(line)) {
-            if iter.any(|line| key_pattern.is_match(line)) {
-                return Err(LinesEditorError::MultipleCandidates);
-            }
-
-            if pair_pattern.is_match(line) {
-                return Ok(ReplaceStatus::AlreadyPresent);
-            }
+        let position = match index.single(key_pattern)? {
+            Some(position) => position,
+            None => return Err(LinesEditorError::NotApplicable(value)),
+        };
 
-            *line = value;
-        } else {
-            return Err(LinesEditorError::NotApplicable(value));
+        if index.matches_at(pair_pattern, position) {
+            return Ok(ReplaceStatus::AlreadyPresent);
         }
 
+        self.lines[position] = value;
         Ok(ReplaceStatus::Replaced)
     }
 
     fn present(
         &mut self,
+        index: &LineIndex,
         value_pattern: &Regex,
         value: String,
         placement: &Placement,
     ) -> Result<PresentStatus, LinesEditorError> {
-        if self.lines.iter().any(|line| value_pattern.is_match(line)) {
+        if index.any(value_pattern) {
             return Ok(PresentStatus::AlreadyPresent);
         }
 
-        match placement {
-            Placement::AtTop => {
-                self.lines.insert(0, value);
-            }
-            Placement::AtEnd => {
-                self.lines.push(value);
-            }
-            Placement::RelativeTo { anchor, relation } => {
-                let mut iter = self.lines.iter();
-                if let Some(position) = (&mut iter).position(|line| anchor.is_match(line)) {
-                    if iter.any(|line| anchor.is_match(line)) {
-                        return Err(LinesEditorError::MultipleCandidates);
-                    }
-
-                    match relation {
-                        AnchorRelation::Before => self.lines.insert(position, value),
-                        AnchorRelation::After => self.lines.insert(position + 1, value),
-                    }
-                } else {
-                    return Err(LinesEditorError::NotApplicable(value));
-                }
-            }
-        }
-
-        Ok(PresentStatus::InsertedPlacement)
+        place_line(&mut self.lines, value, placement, |_, anchor| {
+            index.single(anchor)
+        })
     }
 
-    fn absent(&mut self, pattern: &Regex) -> Result<AbsentStatus, LinesEditorError> {
-        let mut iter = self.lines.iter();
-        if let Some(position) = (&mut iter).position(|line| pattern.is_match(line)) {
-            if iter.any(|line| pattern.is_match(line)) {
-                return Err(LinesEditorError::MultipleCandidates);
-            }
-
+    fn absent(
+        &mut self,
+        index: &LineIndex,
+        pattern: &Regex,
+    ) -> Result<AbsentStatus, LinesEditorError> {
+        if let Some(position) = index.single(pattern)? {
             self.lines.remove(position);
         } else {
             return Ok(AbsentStatus::AlreadyAbsent);
@@ -122,21 +499,19 @@ impl LinesEditor {
         ignore_whitespace: bool,
         ensure: Ensure,
     ) -> Result<EditStatus, LinesEditorError> {
-        let value_pattern = Regex::new(&if ignore_whitespace {
-            format!(r#"^\s*{}\s*$"#, &regex::escape(&value))
-        } else {
-            format!(r#"^{}$"#, &regex::escape(&value))
-        })
-        .expect("failed to construct absent regex");
+        let value_pattern = line_pattern(&value, ignore_whitespace);
 
         let status = match ensure {
             Ensure::Present { placement } => {
                 info!("Ensuring line {:?} is preset", value);
-                self.present(&value_pattern, value, &placement)?.into()
+                let index = self.index(&[&value_pattern], Some(&placement));
+                self.present(&index, &value_pattern, value, &placement)?
+                    .into()
             }
             Ensure::Absent => {
                 info!("Ensuring line {:?} is absent", value);
-                self.absent(&value_pattern)?.into()
+                let index = self.index(&[&value_pattern], None);
+                self.absent(&index, &value_pattern)?.into()
             }
         };
 
@@ -152,68 +527,400 @@ impl LinesEditor {
         separator: &Regex,
         ensure: Ensure,
     ) -> Result<EditStatus, LinesEditorError> {
-        let (key, value) = separator
-            .splitn(&pair, 2)
-            .collect_tuple()
-            .ok_or(LinesEditorError::InvalidPairOrSeparator)?;
+        let (key, value) = split_pair(&pair, separator)?;
 
-        let pair_pattern = Regex::new(&if ignore_whitespace {
-            format!(
-                r#"^\s*{}{}{}\s*$"#,
-                regex::escape(key),
-                separator,
-                regex::escape(value)
-            )
-        } else {
-            format!(
-                r#"^{}{}{}$"#,
-                regex::escape(key),
-                separator,
-                regex::escape(value)
-            )
-        })
-        .expect("failed to construct pair_pattern regex");
+        let pair_pattern = pair_pattern(key, value, ignore_whitespace, separator);
 
         let replace_pattern = if multikey {
             // Replace only for full key-value match
             pair_pattern.clone()
         } else {
-            Regex::new(&if ignore_whitespace {
-                format!(r#"^\s*{}{}"#, regex::escape(key), separator)
-            } else {
-                format!(r#"^{}{}"#, regex::escape(key), separator)
-            })
-            .expect("failed to construct replace_pattern regex")
+            key_pattern(key, ignore_whitespace, separator)
         };
 
         let status = match ensure {
             Ensure::Present { placement } => {
                 info!("Ensuring key and value pair {:?} is preset", pair);
-                match self.replaced(&pair_pattern, &replace_pattern, pair) {
-                    Err(LinesEditorError::NotApplicable(pair)) => {
-                        self.present(&pair_pattern, pair, &placement)?.into()
-                    }
+                // Lines are matched once; index stays valid as present is only tried if nothing was replaced
+                let index = self.index(&[&pair_pattern, &replace_pattern], Some(&placement));
+                match self.replaced(&index, &pair_pattern, &replace_pattern, pair) {
+                    Err(LinesEditorError::NotApplicable(pair)) => self
+                        .present(&index, &pair_pattern, pair, &placement)?
+                        .into(),
                     Err(err) => return Err(err),
                     Ok(status) => status.into(),
                 }
             }
             Ensure::Absent => {
                 info!("Ensuring key and value pair {:?} is absent", pair);
-                self.absent(&pair_pattern)?.into()
+                let index = self.index(&[&pair_pattern], None);
+                self.absent(&index, &pair_pattern)?.into()
             }
         };
 
         debug!("Edit pair:\n{:?}:\n{:#?}", status, self);
         Ok(status)
     }
+
+    /// All lines that can be split into key and value pair with given separator, skipping blank lines and lines
+    /// starting with comment prefix
+    pub fn pairs(
+        &self,
+        ignore_whitespace: bool,
+        separator: &Regex,
+        comment: Option<&str>,
+    ) -> Vec<Pair> {
+        let mut pairs = Vec::new();
+        let mut line_number = 1;
+
+        for line in &self.lines {
+            let number = line_number;
+            line_number += line.matches(NEW_LINE).count() + 1;
+
+            let line = logical(line);
+            let trimmed = line.trim_start();
+            if trimmed.is_empty()
+                || matches!(comment, Some(comment) if trimmed.starts_with(comment))
+            {
+                continue;
+            }
+
+            if let Ok((key, value)) = split_pair(&line, separator) {
+                let (key, value) = if ignore_whitespace {
+                    (key.trim_start(), value.trim_end())
+                } else {
+                    (key, value)
+                };
+                pairs.push(Pair {
+                    line: number,
+                    key: key.to_owned(),
+                    value: value.to_owned(),
+                });
+            }
+        }
+
+        pairs
+    }
+
+    /// Values of all lines with given key; there can be only one unless multikey is set
+    pub fn get_values(
+        &self,
+        key: &str,
+        multikey: bool,
+        ignore_whitespace: bool,
+        separator: &Regex,
+    ) -> Result<Vec<String>, LinesEditorError> {
+        let key_pattern = key_pattern(key, ignore_whitespace, separator);
+
+        let values = self
+            .lines
+            .iter()
+            .filter_map(|line| {
+                let line = logical(line);
+                let value = &line[key_pattern.find(&line)?.end()..];
+                Some(if ignore_whitespace {
+                    value.trim_end().to_owned()
+                } else {
+                    value.to_owned()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if values.len() > 1 && !multikey {
+            return Err(LinesEditorError::MultipleCandidates);
+        }
+
+        debug!("Get key {:?}: {:?}", key, values);
+        Ok(values)
+    }
+
+    pub fn edit_list_item(
+        &mut self,
+        pair: String,
+        ignore_whitespace: bool,
+        separator: &Regex,
+        delimiter: &str,
+        quote: Option<char>,
+        ensure: Ensure,
+    ) -> Result<EditStatus, LinesEditorError> {
+        let (key, item) = split_pair(&pair, separator)?;
+        let pair_separator = separator
+            .find(&pair)
+            .ok_or(LinesEditorError::InvalidPairOrSeparator)?
+            .as_str();
+
+        let key_pattern = Regex::new(&if ignore_whitespace {
+            format!(
+                r#"^(?P<prefix>\s*{}{})(?P<value>.*?)(?P<suffix>\s*)$"#,
+                regex::escape(key),
+                separator
+            )
+        } else {
+            format!(
+                r#"^(?P<prefix>{}{})(?P<value>.*)(?P<suffix>)$"#,
+                regex::escape(key),
+                separator
+            )
+        })
+        .expect("failed to construct key_pattern regex");
+
+        let position = self.index(&[&key_pattern], None).single(&key_pattern)?;
+
+        let status = match (ensure, position) {
+            (Ensure::Present { placement }, None) => {
+                info!("Ensuring key {:?} with list item {:?} is preset", key, item);
+                let value = match quote {
+                    Some(quote) => format!("{}{}{}", quote, item, quote),
+                    None => item.to_owned(),
+                };
+                let new_line = format!("{}{}{}", key, pair_separator, value);
+                let value_pattern = Regex::new(&format!(r#"^{}$"#, regex::escape(&new_line)))
+                    .expect("failed to construct value_pattern regex");
+                let index = self.index(&[&value_pattern], Some(&placement));
+                self.present(&index, &value_pattern, new_line, &placement)?
+                    .into()
+            }
+            (Ensure::Absent, None) => {
+                info!("Ensuring key {:?} with list item {:?} is absent", key, item);
+                AbsentStatus::AlreadyAbsent.into()
+            }
+            (ensure, Some(position)) => {
+                let captures = key_pattern
+                    .captures(&self.lines[position])
+                    .expect("key_pattern match");
+                let prefix = &captures["prefix"];
+                let suffix = &captures["suffix"];
+                let mut value = &captures["value"];
+
+                let quoted = match quote {
+                    Some(quote)
+                        if value.len() > 1
+                            && value.starts_with(quote)
+                            && value.ends_with(quote) =>
+                    {
+                        value = &value[quote.len_utf8()..value.len() - quote.len_utf8()];
+                        true
+                    }
+                    _ => false,
+                };
+
+                // Other items are kept as they are, including empty ones (e.g. repeated delimiter)
+                let mut items = if value.is_empty() {
+                    Vec::new()
+                } else {
+                    value.split(delimiter).collect::<Vec<_>>()
+                };
+
+                let status: EditStatus = match ensure {
+                    Ensure::Present { .. } => {
+                        info!("Ensuring key {:?} with list item {:?} is preset", key, item);
+                        if items.contains(&item) {
+                            PresentStatus::AlreadyPresent.into()
+                        } else {
+                            items.push(item);
+                            PresentStatus::InsertedPlacement.into()
+                        }
+                    }
+                    Ensure::Absent => {
+                        info!("Ensuring key {:?} with list item {:?} is absent", key, item);
+                        if items.contains(&item) {
+                            items.retain(|list_item| *list_item != item);
+                            AbsentStatus::Removed.into()
+                        } else {
+                            AbsentStatus::AlreadyAbsent.into()
+                        }
+                    }
+                };
+
+                if status.has_changed() {
+                    if items.is_empty() {
+                        // Line would be left with empty value after its last item was removed
+                        self.lines.remove(position);
+                    } else {
+                        let mut value = items.join(delimiter);
+                        if let Some(quote) = quote.filter(|_| quoted) {
+                            value = format!("{}{}{}", quote, value, quote);
+                        }
+                        self.lines[position] = format!("{}{}{}", prefix, value, suffix);
+                    }
+                }
+
+                status
+            }
+        };
+
+        debug!("Edit list item:\n{:?}:\n{:#?}", status, self);
+        Ok(status)
+    }
+
+    pub fn edit_columns(
+        &mut self,
+        record: String,
+        key_columns: &[usize],
+        value_columns: &[usize],
+        ensure: Ensure,
+    ) -> Result<EditStatus, LinesEditorError> {
+        let record_fields = fields(&record)
+            .into_iter()
+            .map(|(_, field)| field)
+            .collect::<Vec<_>>();
+
+        if let Some(column) = key_columns
+            .iter()
+            .chain(value_columns)
+            .find(|column| **column == 0 || **column > record_fields.len())
+        {
+            return Err(LinesEditorError::InvalidKey(format!("column {}", column)));
+        }
+
+        // Key columns identify the record; it is matched together with value columns to tell if it has the values
+        let key_pattern = columns_pattern(&record_fields, key_columns);
+        let with_key_columns = |value_columns: &[usize]| {
+            key_columns
+                .iter()
+                .chain(value_columns)
+                .copied()
+                .collect::<Vec<_>>()
+        };
+
+        let status = match ensure {
+            Ensure::Present { placement } => {
+                info!("Ensuring record {:?} is preset", record);
+                let value_columns = if value_columns.is_empty() {
+                    (1..=record_fields.len())
+                        .filter(|column| !key_columns.contains(column))
+                        .collect()
+                } else {
+                    value_columns.to_vec()
+                };
+                let record_pattern =
+                    columns_pattern(&record_fields, &with_key_columns(&value_columns));
+                let line_pattern = Regex::new(&format!(
+                    r#"^\s*{}\s*$"#,
+                    record_fields
+                        .iter()
+                        .map(|field| regex::escape(field))
+                        .join(r"\s+")
+                ))
+                .expect("failed to construct line_pattern regex");
+
+                let index = self.index(
+                    &[&key_pattern, &record_pattern, &line_pattern],
+                    Some(&placement),
+                );
+                if let Some(position) = index.single(&key_pattern)? {
+                    if index.matches_at(&record_pattern, position) {
+                        ReplaceStatus::AlreadyPresent.into()
+                    } else {
+                        self.lines[position] =
+                            replace_fields(&self.lines[position], &value_columns, &record_fields);
+                        ReplaceStatus::Replaced.into()
+                    }
+                } else {
+                    self.present(&index, &line_pattern, record, &placement)?
+                        .into()
+                }
+            }
+            Ensure::Absent => {
+                // Record is identified by key columns alone unless value columns were given
+                info!("Ensuring record {:?} is absent", record);
+                let record_pattern =
+                    columns_pattern(&record_fields, &with_key_columns(value_columns));
+                let index = self.index(&[&record_pattern], None);
+                self.absent(&index, &record_pattern)?.into()
+            }
+        };
+
+        debug!("Edit columns:\n{:?}:\n{:#?}", status, self);
+        Ok(status)
+    }
+}
+
+/// Pattern matching lines that are not comments and have fields of the record in given columns
+fn columns_pattern(record_fields: &[&str], columns: &[usize]) -> Regex {
+    let last_column = columns.iter().copied().max().unwrap_or(0);
+    let fields = (1..=last_column)
+        .map(|column| {
+            if columns.contains(&column) {
+                regex::escape(record_fields[column - 1])
+            } else if column == 1 {
+                r"[^#\s]\S*".to_owned()
+            } else {
+                r"\S+".to_owned()
+            }
+        })
+        .join(r"\s+");
+
+    Regex::new(&format!(r"^\s*{}(?:\s|$)", fields))
+        .expect("failed to construct columns_pattern regex")
+}
+
+/// White space separated fields of the line with their starting character column
+fn fields(line: &str) -> Vec<(usize, &str)> {
+    let mut fields = Vec::new();
+    let mut start = None;
+
+    for (column, (index, c)) in line.char_indices().enumerate() {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some((column, index)),
+            (Some((start_column, start_index)), true) => {
+                fields.push((start_column, &line[start_index..index]));
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    if let Some((start_column, start_index)) = start {
+        fields.push((start_column, &line[start_index..]));
+    }
+
+    fields
+}
+
+/// Replaces fields in given columns keeping other fields starting at their original character columns where possible
+fn replace_fields(line: &str, columns: &[usize], record_fields: &[&str]) -> String {
+    let line_fields = fields(line);
+    let mut new_line = line[..line.len() - line.trim_start().len()].to_owned();
+
+    for (index, (start_column, field)) in line_fields.iter().enumerate() {
+        if index > 0 {
+            let (prev_start, prev_field) = line_fields[index - 1];
+            let separator_start = prev_start + prev_field.chars().count();
+            let separator = line
+                .chars()
+                .skip(separator_start)
+                .take(start_column - separator_start)
+                .collect::<String>();
+
+            if separator.contains('\t') {
+                new_line.push_str(&separator);
+            } else {
+                let padding = start_column.saturating_sub(new_line.chars().count()).max(1);
+                new_line.push_str(&" ".repeat(padding));
+            }
+        }
+
+        if columns.contains(&(index + 1)) {
+            new_line.push_str(record_fields[index]);
+        } else {
+            new_line.push_str(field);
+        }
+    }
+
+    // Line is missing some columns that need to be set; extend it only up to the last of them
+    let last_column = columns.iter().copied().max().unwrap_or(0);
+    if last_column > line_fields.len() {
+        for field in &record_fields[line_fields.len()..last_column] {
+            new_line.push(' ');
+            new_line.push_str(field);
+        }
+    }
+
+    new_line
 }
 
 impl fmt::Display for LinesEditor {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for line in &self.lines {
-            f.write_str(line)?;
-            f.write_str(NEW_LINE)?;
-        }
-        Ok(())
+        write_lines(f, &self.lines)
     }
 }
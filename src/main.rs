@@ -1,17 +1,38 @@
 use cotton::prelude::result::Result as PResult;
 use cotton::prelude::*;
 
-use diff::Result::*;
+use glob::Pattern;
 use regex::Regex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
 use std::io::Cursor;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 
+mod ansible;
 mod editor;
+mod encoding;
+mod env_editor;
+mod files;
+mod git_config_editor;
 mod lines_editor;
+mod pipeline;
+mod properties_editor;
 
 use editor::{EditStatus, Ensure};
-use lines_editor::LinesEditor;
-
-#[derive(Debug, StructOpt)]
+use encoding::Encoding;
+use env_editor::EnvEditor;
+use git_config_editor::GitConfigEditor;
+use lines_editor::{LinesEditor, LinesEditorError};
+use pipeline::{
+    edit_file, prepare_edit, problem_message, run_on_change, write_edit, ChangedLines, Outcome,
+};
+use properties_editor::PropertiesEditor;
+
+#[derive(Debug, Clone, StructOpt, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
 enum Edit {
     /// Edit line in text file
     Line {
@@ -20,6 +41,9 @@ enum Edit {
         /// Ignore any white space at the beginning and end of each file line
         #[structopt(long, short = "w")]
         ignore_whitespace: bool,
+        /// Treat lines ending with backslash as continued on the next line
+        #[structopt(long, short = "l")]
+        continuation: bool,
         #[structopt(flatten)]
         ensure: Ensure,
     },
@@ -33,16 +57,185 @@ enum Edit {
         /// Ignore any white space at the beginning and end of each file line
         #[structopt(long, short = "w")]
         ignore_whitespace: bool,
+        /// Treat lines ending with backslash as continued on the next line
+        #[structopt(long, short = "l")]
+        continuation: bool,
+        /// Regular expression pattern matching separator of key and value pairs
+        #[structopt(long, short, default_value = r#"(\s*=\s*)"#)]
+        #[serde(serialize_with = "editor::serialize_regex")]
+        separator: Regex,
+        #[structopt(flatten)]
+        ensure: Ensure,
+    },
+    /// Edit single item of delimited list value in line containing key and value pair; line is removed with its last item
+    ListItem {
+        /// Key and list item pair
+        pair: String,
+        /// Ignore any white space at the beginning and end of each file line
+        #[structopt(long, short = "w")]
+        ignore_whitespace: bool,
         /// Regular expression pattern matching separator of key and value pairs
         #[structopt(long, short, default_value = r#"(\s*=\s*)"#)]
+        #[serde(serialize_with = "editor::serialize_regex")]
         separator: Regex,
+        /// Delimiter of list items
+        #[structopt(long, short, default_value = " ")]
+        delimiter: String,
+        /// Quote character that may surround the list value; existing value is quoted only if it already was
+        #[structopt(long, short)]
+        quote: Option<char>,
+        #[structopt(flatten)]
+        ensure: Ensure,
+    },
+    /// Edit record in text file with white space separated columns
+    Columns {
+        /// White space separated record
+        record: String,
+        /// Number of column (starting from 1) identifying the record; can be given multiple times
+        #[structopt(long, short, required = true, number_of_values = 1)]
+        key: Vec<usize>,
+        /// Number of column that is ensured to have value from the record; defaults to all non-key columns; absent record is matched by key columns only unless given
+        #[structopt(long = "value", short = "V", number_of_values = 1)]
+        value: Vec<usize>,
         #[structopt(flatten)]
         ensure: Ensure,
     },
+    /// Edit key in git-config style INI file; placement is relative to the key's section
+    GitConfig {
+        /// Key in form of section.key or section.subsection.key
+        key: String,
+        /// Value of the key
+        value: String,
+        /// Manage values of multi-valued key as a set
+        #[structopt(long, short)]
+        multikey: bool,
+        #[structopt(flatten)]
+        ensure: Ensure,
+    },
+    /// Edit property in Java properties file
+    Properties {
+        /// Property key
+        key: String,
+        /// Property value
+        value: String,
+        /// Write non-ASCII characters as unicode escapes
+        #[structopt(long, short = "u")]
+        escape_unicode: bool,
+        #[structopt(flatten)]
+        ensure: Ensure,
+    },
+    /// Edit variable in shell-style environment file
+    Env {
+        /// Variable name
+        key: String,
+        /// Variable value; compared after shell-unquoting
+        value: String,
+        /// Prefix newly inserted variable with export keyword
+        #[structopt(long, short)]
+        export: bool,
+        #[structopt(flatten)]
+        ensure: Ensure,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    #[structopt(flatten)]
+    Edit(Edit),
+    /// Print value of key in text file containing key and value pairs
+    Get {
+        /// Key to print value of
+        key: String,
+        /// Print values of all lines with the key, each on separate line
+        #[structopt(long, short)]
+        multikey: bool,
+        /// Ignore any white space at the beginning and end of each file line
+        #[structopt(long, short = "w")]
+        ignore_whitespace: bool,
+        /// Treat lines ending with backslash as continued on the next line
+        #[structopt(long, short = "l")]
+        continuation: bool,
+        /// Regular expression pattern matching separator of key and value pairs
+        #[structopt(long, short, default_value = r#"(\s*=\s*)"#)]
+        separator: Regex,
+    },
+    /// Test if line or key and value pair is present in text file without editing it
+    Test(Test),
+    /// Print all key and value pairs of text file with their line numbers as JSON
+    Dump {
+        /// Ignore any white space at the beginning and end of each file line
+        #[structopt(long, short = "w")]
+        ignore_whitespace: bool,
+        /// Treat lines ending with backslash as continued on the next line
+        #[structopt(long, short = "l")]
+        continuation: bool,
+        /// Regular expression pattern matching separator of key and value pairs
+        #[structopt(long, short, default_value = r#"(\s*=\s*)"#)]
+        separator: Regex,
+        /// Skip lines starting with this prefix (ignoring leading white space)
+        #[structopt(long)]
+        comment: Option<String>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum Test {
+    /// Test if line is present in text file
+    Line {
+        /// Line of text
+        value: String,
+        /// Ignore any white space at the beginning and end of each file line
+        #[structopt(long, short = "w")]
+        ignore_whitespace: bool,
+        /// Treat lines ending with backslash as continued on the next line
+        #[structopt(long, short = "l")]
+        continuation: bool,
+    },
+    /// Test if key and value pair is present in text file
+    LinePair {
+        /// Key and value pair
+        pair: String,
+        /// Ignore any white space at the beginning and end of each file line
+        #[structopt(long, short = "w")]
+        ignore_whitespace: bool,
+        /// Treat lines ending with backslash as continued on the next line
+        #[structopt(long, short = "l")]
+        continuation: bool,
+        /// Regular expression pattern matching separator of key and value pairs
+        #[structopt(long, short, default_value = r#"(\s*=\s*)"#)]
+        separator: Regex,
+    },
 }
 
+/// Failure other than listed below, e.g. invalid arguments or input that can not be decoded
+const EXIT_FAILURE: i32 = 1;
+/// File was changed (or would have changed in check mode)
+const EXIT_CHANGED: i32 = 2;
+const EXIT_MULTIPLE_CANDIDATES: i32 = 3;
+const EXIT_NOT_APPLICABLE: i32 = 4;
+const EXIT_INVALID_PAIR_OR_SEPARATOR: i32 = 5;
+/// Failed to read, lock, validate or write a file or to run on change command
+const EXIT_IO: i32 = 6;
+const EXIT_INVALID_KEY: i32 = 7;
+/// Key was not found by get command or line or pair by test command
+const EXIT_NOT_FOUND: i32 = 8;
+
+const EXIT_STATUS_HELP: &str = "EXIT STATUS:
+    0    Success; file was not changed (or was changed without --detailed-exit-codes)
+    1    Other failure; e.g. invalid arguments or input that is not valid text in given encoding
+    2    File was changed with --detailed-exit-codes or would have changed with --check
+    3    Multiple candidates for the edit found in the file
+    4    Edit not applicable; e.g. anchor not found
+    5    Failed to split value as key and value pair with given separator
+    6    Failed to read, lock, validate or write file or to run on change command
+    7    Invalid key
+    8    Key not found (get) or line or pair not present (test)
+
+When editing multiple files, status of the first file that failed is used.";
+
 /// Declaratively applies edits to files of various formats
 #[derive(Debug, StructOpt)]
+#[structopt(after_help = EXIT_STATUS_HELP)]
 struct Cli {
     #[structopt(flatten)]
     logging: LoggingOpt,
@@ -51,183 +244,900 @@ struct Cli {
     #[structopt(long, short)]
     check: bool,
 
+    /// Exit with status 2 if file was changed; see EXIT STATUS for all exit statuses
+    #[structopt(long)]
+    detailed_exit_codes: bool,
+
     /// Print difference from before and after edit
     #[structopt(long, short)]
     diff: bool,
 
-    /// Edit this file in place.
-    #[structopt(long, short)]
-    in_place: Option<PathBuf>,
+    /// Read input from this file instead of standard input
+    #[structopt(long, short = "I", conflicts_with_all = &["in-place", "recursive"])]
+    input: Option<PathBuf>,
+
+    /// Write output to this file instead of standard output; parent directories are created as needed
+    #[structopt(long, short, conflicts_with_all = &["in-place", "recursive"])]
+    output: Option<PathBuf>,
+
+    /// Edit this file in place; can be given multiple times and accepts glob patterns that have to match some files
+    #[structopt(long, short, number_of_values = 1)]
+    in_place: Vec<String>,
+
+    /// Edit files in this directory and its subdirectories in place; can be given multiple times
+    #[structopt(long, short, number_of_values = 1)]
+    recursive: Vec<PathBuf>,
+
+    /// Edit only files with name (or relative path if pattern contains /) matching this glob pattern in recursive mode
+    #[structopt(long, number_of_values = 1)]
+    include: Vec<Pattern>,
+
+    /// Skip files and directories with name (or relative path if pattern contains /) matching this glob pattern in recursive mode
+    #[structopt(long, number_of_values = 1)]
+    exclude: Vec<Pattern>,
+
+    /// Validate edited file with this shell command before replacing the original; %s is replaced with path to temporary file
+    #[structopt(long)]
+    validate: Option<String>,
 
-    /// Create in-place file is it does not exist
+    /// Run this command with shell after file was changed; PEDIT_FILE and PEDIT_STATUS environment variables are set
+    #[structopt(long)]
+    on_change: Option<String>,
+
+    /// Hold exclusive lock on <file>.lock while editing file to serialize concurrent edits
+    #[structopt(long, short = "L")]
+    lock: bool,
+
+    /// Number of seconds to wait for lock held by other process
+    #[structopt(long, default_value = "10")]
+    lock_timeout: u64,
+
+    /// Print report of edit results in given format (json) to standard output
+    #[structopt(long, possible_values = &["json"])]
+    report: Option<String>,
+
+    /// Number of files to edit in parallel
+    #[structopt(long, short, default_value = "1")]
+    jobs: usize,
+
+    /// Edit all files before writing any of them and restore files already written if writing of other file fails
+    #[structopt(long)]
+    transactional: bool,
+
+    /// Character encoding of files (utf-8 or latin1); with latin1 any file can be edited and untouched lines are written back byte for byte
+    #[structopt(long, short = "E", default_value = "utf-8")]
+    encoding: Encoding,
+
+    /// Edit line by line without reading whole file to memory if edit allows it (line and line-pair edits that are absent or present at-end); other edits, output to standard output and --diff or --report read whole file
+    #[structopt(long)]
+    stream: bool,
+
+    /// Create in-place or input file if it does not exist
     #[structopt(long, short = "C")]
     create: bool,
 
+    /// Create missing parent directories of file created with --create
+    #[structopt(long, requires = "create")]
+    create_dirs: bool,
+
+    /// Permissions of newly created file as octal number, e.g. 640; default permissions are subject to umask
+    #[structopt(long, parse(try_from_str = parse_mode))]
+    mode: Option<u32>,
+
+    /// Owner (user name or id) of newly created file
+    #[structopt(long)]
+    owner: Option<String>,
+
+    /// Group (name or id) of newly created file
+    #[structopt(long)]
+    group: Option<String>,
+
     #[structopt(subcommand)]
-    edit: Edit,
+    command: Command,
+}
+
+impl Cli {
+    /// How to create written file if it does not exist; parent directories of output file are always created
+    fn new_file(&self) -> files::NewFile {
+        files::NewFile {
+            create_dirs: self.create_dirs || self.output.is_some(),
+            mode: self.mode,
+            owner: self.owner.clone(),
+            group: self.group.clone(),
+        }
+    }
+}
+
+/// Parses file permissions given as octal number
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("invalid octal file mode {:?}", mode)),
+    }
+}
+
+fn load_lines(input: impl Read, continuation: bool) -> Result<LinesEditor, EditError> {
+    if continuation {
+        LinesEditor::load_continued(input)
+    } else {
+        LinesEditor::load(input)
+    }
+    .map_err(|err| EditError::read(err, "reading input text file"))
+}
+
+/// Failure of an edit keeping errors reported by editors and I/O errors apart from other problems
+#[derive(Debug)]
+enum EditError {
+    Editor(LinesEditorError),
+    /// Failure to read, lock, validate or write a file or to run on change command
+    Io(Problem),
+    Other(Problem),
+}
+
+impl EditError {
+    /// Name of the kind of error used in the report
+    fn kind(&self) -> &'static str {
+        match self {
+            EditError::Editor(LinesEditorError::InvalidPairOrSeparator) => "InvalidPairOrSeparator",
+            EditError::Editor(LinesEditorError::MultipleCandidates) => "MultipleCandidates",
+            EditError::Editor(LinesEditorError::NotApplicable(_)) => "NotApplicable",
+            EditError::Editor(LinesEditorError::InvalidKey(_)) => "InvalidKey",
+            EditError::Io(_) => "Io",
+            EditError::Other(_) => "Other",
+        }
+    }
+
+    fn exit_status(&self) -> i32 {
+        match self {
+            EditError::Editor(LinesEditorError::InvalidPairOrSeparator) => {
+                EXIT_INVALID_PAIR_OR_SEPARATOR
+            }
+            EditError::Editor(LinesEditorError::MultipleCandidates) => EXIT_MULTIPLE_CANDIDATES,
+            EditError::Editor(LinesEditorError::NotApplicable(_)) => EXIT_NOT_APPLICABLE,
+            EditError::Editor(LinesEditorError::InvalidKey(_)) => EXIT_INVALID_KEY,
+            EditError::Io(_) => EXIT_IO,
+            EditError::Other(_) => EXIT_FAILURE,
+        }
+    }
+
+    /// Error of reading input; input that is not valid text is not an I/O error
+    fn read(err: std::io::Error, message: &str) -> EditError {
+        let invalid_data = err.kind() == std::io::ErrorKind::InvalidData;
+        let problem = Problem::from_error(err).problem_while(message);
+        if invalid_data {
+            EditError::Other(problem)
+        } else {
+            EditError::Io(problem)
+        }
+    }
+
+    /// Fails with exit status of the error
+    fn fatal<T>(self) -> Result<T, FatalProblem> {
+        let exit_status = self.exit_status();
+        Err(Problem::from_error(self)).fatal_with_status(exit_status)
+    }
+}
+
+impl From<LinesEditorError> for EditError {
+    fn from(err: LinesEditorError) -> EditError {
+        EditError::Editor(err)
+    }
+}
+
+impl From<Problem> for EditError {
+    fn from(problem: Problem) -> EditError {
+        EditError::Other(problem)
+    }
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditError::Editor(err) => write!(f, "{}", err),
+            EditError::Io(problem) | EditError::Other(problem) => write!(f, "{}", problem),
+        }
+    }
 }
 
-fn edit(input: impl Read, edit: Edit) -> PResult<(Box<dyn Display>, EditStatus)> {
-    let mut editor = LinesEditor::load(input).problem_while("reading input text file")?;
+impl Error for EditError {}
 
-    let status = match edit {
+fn edit(input: impl Read, edit: Edit) -> Result<(Box<dyn Display>, EditStatus), EditError> {
+    Ok(match edit {
         Edit::Line {
             value,
             ignore_whitespace,
+            continuation,
             ensure,
-        } => editor.edit_line(value, ignore_whitespace, ensure)?,
+        } => {
+            let mut editor = load_lines(input, continuation)?;
+            let status = editor.edit_line(value, ignore_whitespace, ensure)?;
+            (Box::new(editor), status)
+        }
         Edit::LinePair {
             pair,
             multikey,
             ignore_whitespace,
+            continuation,
             separator,
             ensure,
-        } => editor.edit_pair(pair, multikey, ignore_whitespace, &separator, ensure)?,
-    };
+        } => {
+            let mut editor = load_lines(input, continuation)?;
+            let status = editor.edit_pair(pair, multikey, ignore_whitespace, &separator, ensure)?;
+            (Box::new(editor), status)
+        }
+        Edit::ListItem {
+            pair,
+            ignore_whitespace,
+            separator,
+            delimiter,
+            quote,
+            ensure,
+        } => {
+            let mut editor = LinesEditor::load(input)
+                .map_err(|err| EditError::read(err, "reading input text file"))?;
+            let status = editor.edit_list_item(
+                pair,
+                ignore_whitespace,
+                &separator,
+                &delimiter,
+                quote,
+                ensure,
+            )?;
+            (Box::new(editor), status)
+        }
+        Edit::Columns {
+            record,
+            key,
+            value,
+            ensure,
+        } => {
+            let mut editor = LinesEditor::load(input)
+                .map_err(|err| EditError::read(err, "reading input text file"))?;
+            let status = editor.edit_columns(record, &key, &value, ensure)?;
+            (Box::new(editor), status)
+        }
+        Edit::GitConfig {
+            key,
+            value,
+            multikey,
+            ensure,
+        } => {
+            let mut editor = GitConfigEditor::load(input)
+                .map_err(|err| EditError::read(err, "reading input git-config file"))?;
+            let status = editor.edit_key(key, value, multikey, ensure)?;
+            (Box::new(editor), status)
+        }
+        Edit::Properties {
+            key,
+            value,
+            escape_unicode,
+            ensure,
+        } => {
+            let mut editor = PropertiesEditor::load(input, escape_unicode)
+                .map_err(|err| EditError::read(err, "reading input properties file"))?;
+            let status = editor.edit_property(key, value, ensure)?;
+            (Box::new(editor), status)
+        }
+        Edit::Env {
+            key,
+            value,
+            export,
+            ensure,
+        } => {
+            let mut editor = EnvEditor::load(input)
+                .map_err(|err| EditError::read(err, "reading input environment file"))?;
+            let status = editor.edit_variable(key, value, export, ensure)?;
+            (Box::new(editor), status)
+        }
+    })
+}
 
-    Ok((Box::new(editor) as Box<dyn Display>, status))
+/// Error as written in the report
+#[derive(Debug, Serialize)]
+struct ErrorReport {
+    kind: &'static str,
+    message: String,
+    #[serde(skip)]
+    exit_status: i32,
 }
 
-//TODO:
-// * tests
-// * stream input to output with no buffering when possible
-// * preserve no line ending on last line
-fn main() -> FinalResult {
-    let args = Cli::from_args();
-    init_logger(&args.logging, vec![module_path!()]);
+impl From<&EditError> for ErrorReport {
+    fn from(err: &EditError) -> ErrorReport {
+        ErrorReport {
+            kind: err.kind(),
+            message: match err {
+                EditError::Editor(err) => err.to_string(),
+                EditError::Io(problem) | EditError::Other(problem) => problem_message(problem),
+            },
+            exit_status: err.exit_status(),
+        }
+    }
+}
 
-    let mut diff_input = None;
-
-    let mut input = args
-        .in_place
-        .as_ref()
-        .map(|file| {
-            match (
-                File::open(file).map(|f| Box::new(f) as Box<dyn Read>),
-                args.create,
-            ) {
-                (Err(_), true) => Ok(Box::new(Cursor::new(String::new())) as Box<dyn Read>),
-                (result, _) => result,
-            }
-        })
-        .transpose()
-        .problem_while("opening file for reading")?
-        .unwrap_or_else(|| Box::new(stdin()) as Box<dyn Read>);
+/// Result of editing single file as written in the report
+#[derive(Debug, Serialize)]
+struct FileReport {
+    /// Edited file or `None` for standard input
+    file: Option<PathBuf>,
+    status: Option<EditStatus>,
+    changed: bool,
+    changed_lines: ChangedLines,
+    error: Option<ErrorReport>,
+}
 
-    if args.diff {
-        let mut input_data = String::new();
-        input
-            .read_to_string(&mut input_data)
-            .problem_while("reading input data")?;
+impl FileReport {
+    fn new(file: Option<&Path>, result: Result<&Outcome, &EditError>) -> FileReport {
+        match result {
+            Ok(outcome) => FileReport {
+                file: file.map(ToOwned::to_owned),
+                status: Some(outcome.status),
+                changed: outcome.status.has_changed(),
+                changed_lines: outcome.changed_lines.clone(),
+                error: None,
+            },
+            Err(err) => FileReport {
+                file: file.map(ToOwned::to_owned),
+                status: None,
+                changed: false,
+                changed_lines: ChangedLines::default(),
+                error: Some(err.into()),
+            },
+        }
+    }
+}
+
+/// Machine readable report of the whole run
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    edit: &'a Edit,
+    files: &'a [FileReport],
+    /// Overall failure of the run
+    error: Option<String>,
+}
 
-        diff_input = Some(input_data);
-        input = Box::new(Cursor::new(diff_input.as_ref().unwrap()));
+/// Prints report to standard output if requested
+fn print_report(
+    args: &Cli,
+    edit: &Edit,
+    files: &[FileReport],
+    error: Option<&Problem>,
+) -> PResult<()> {
+    if args.report.is_none() {
+        return Ok(());
     }
 
-    let (edited, status) = edit(input, args.edit)?;
+    let report = Report {
+        edit,
+        files,
+        error: error.map(problem_message),
+    };
+    println!(
+        "{}",
+        serde_json::to_string(&report).problem_while("serializing report")?
+    );
+    Ok(())
+}
 
-    info!("Edit result: {}", status);
+/// Counts of edited files by result
+#[derive(Debug, Default)]
+struct Summary {
+    changed: usize,
+    unchanged: usize,
+    failed: usize,
+}
 
-    if let Some(input_data) = diff_input.as_ref() {
-        if status.has_changed() {
-            let output_data = edited.to_string();
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} changed, {} unchanged, {} failed",
+            self.changed, self.unchanged, self.failed
+        )
+    }
+}
 
-            for diff in diff::lines(input_data, &output_data) {
-                match diff {
-                    Left(line) => eprintln!("- {}", line),
-                    Both(line, _) => eprintln!("  {}", line),
-                    Right(line) => eprintln!("+ {}", line),
+/// Applies edit to each of the files independently, possibly in parallel, reporting result of each
+fn edit_files(
+    args: &Cli,
+    edit: &Edit,
+    files: &[PathBuf],
+    reports: &mut Vec<FileReport>,
+) -> Summary {
+    let summary = Mutex::new(Summary::default());
+    let file_reports = Mutex::new(Vec::with_capacity(files.len()));
+    let next = AtomicUsize::new(0);
+
+    let worker = || loop {
+        let index = next.fetch_add(1, Ordering::SeqCst);
+        let file = match files.get(index) {
+            Some(file) => file,
+            None => break,
+        };
+
+        let result = edit_file(args, edit, Some(file), Some(file), true);
+        file_reports
+            .lock()
+            .unwrap()
+            .push((index, FileReport::new(Some(file), result.as_ref())));
+
+        let mut summary = summary.lock().unwrap();
+        match result {
+            Ok(Outcome { status, .. }) => {
+                info!("{}: {}", file.display(), status);
+                if status.has_changed() {
+                    summary.changed += 1;
+                } else {
+                    summary.unchanged += 1;
                 }
             }
+            Err(err) => {
+                error!("{}: {}", file.display(), err);
+                summary.failed += 1;
+            }
         }
-    }
+    };
 
-    if args.check {
-        if status.has_changed() {
-            Err(Problem::from_error("File would have changed (check)")).fatal_with_status(2)?;
-        }
+    let jobs = args.jobs.max(1).min(files.len());
+    if jobs > 1 {
+        thread::scope(|scope| {
+            for _ in 0..jobs {
+                scope.spawn(worker);
+            }
+        });
     } else {
-        let mut output = args
-            .in_place
-            .as_ref()
-            .map(|file| File::create(file).map(|f| Box::new(f) as Box<dyn Write>))
-            .transpose()
-            .problem_while("opening file for writing")?
-            .unwrap_or_else(|| Box::new(stdout()) as Box<dyn Write>);
-
-        write!(output, "{}", edited)?;
+        worker();
     }
 
-    Ok(())
+    // Files edited in parallel finish out of order
+    let mut file_reports = file_reports.into_inner().unwrap();
+    file_reports.sort_by_key(|(index, _)| *index);
+    reports.extend(file_reports.into_iter().map(|(_, report)| report));
+
+    summary.into_inner().unwrap()
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Applies edit to all files in memory first and writes them only if all edits succeeded; if writing
+/// of any file fails files already written are restored to their original content
+fn edit_files_transactional(
+    args: &Cli,
+    edit: &Edit,
+    files: &[PathBuf],
+    reports: &mut Vec<FileReport>,
+) -> PResult<Summary> {
+    let mut summary = Summary::default();
+    let mut pending = Vec::with_capacity(files.len());
+
+    for file in files {
+        let result = prepare_edit(args, edit, Some(file), Some(file));
+        reports.push(FileReport::new(
+            Some(file),
+            result.as_ref().map(|pending_edit| &pending_edit.outcome),
+        ));
+        match result {
+            Ok(pending_edit) => {
+                if args.diff {
+                    pending_edit.print_diff(Some(file));
+                }
+                pending.push((file, pending_edit))
+            }
+            Err(err) => {
+                error!("{}: {}", file.display(), err);
+                summary.failed += 1;
+            }
+        }
+    }
+    if summary.failed > 0 {
+        return problem!(
+            "Failed to edit {} file(s); no files were written",
+            summary.failed
+        );
+    }
 
-    const XML_TEST: &str =
-r#"<LayoutModificationTemplate
-    xmlns="http://schemas.microsoft.com/Start/2014/LayoutModification"
-    xmlns:defaultlayout="http://schemas.microsoft.com/Start/2014/FullDefaultLayout"
-    xmlns:start="http://schemas.microsoft.com/Start/2014/StartLayout"
-    xmlns:taskbar="http://schemas.microsoft.com/Start/2014/TaskbarLayout"
-    Version="1">
-  <CustomTaskbarLayoutCollection PinListPlacement="Replace">
-    <defaultlayout:TaskbarLayout>
-      <taskbar:TaskbarPinList>
-        <taskbar:DesktopApp DesktopApplicationLinkPath="C:\Users\Administrator\AppData\Roaming\Microsoft\Windows\Start Menu\Programs\Scoop Apps\Process Explorer.lnk" />
-        <taskbar:DesktopApp DesktopApplicationLinkPath="C:\ProgramData\Microsoft\Windows\Start Menu\Programs\Administrative Tools\IIS Manager.lnk" />
-      </taskbar:TaskbarPinList>
-    </defaultlayout:TaskbarLayout>
-  </CustomTaskbarLayoutCollection>
-</LayoutModificationTemplate>"#;
+    let mut written: Vec<files::Backup> = Vec::new();
+    for (index, (file, edit)) in pending.iter().enumerate() {
+        if !edit.outcome.status.has_changed() {
+            continue;
+        }
 
-    const SSH_TEST: &str = r#"UserKnownHostsFile /dev/null
-StrictHostKeyChecking no
-IdentityFile ~/.ssh/foo
-IdentityFile ~/.ssh/bar
+        let backup = files::backup(file)?;
+        // Write that failed has not replaced the file so only files written before need restoring
+        if let Err(err) = write_edit(args, Some(file), Some(file), edit) {
+            error!("{}: {}", file.display(), err);
+            reports[index].error = Some((&err).into());
+
+            let mut not_restored = Vec::new();
+            for backup in written.iter().rev() {
+                if let Err(problem) = backup.restore() {
+                    error!(
+                        "{}: failed to restore: {}",
+                        backup.path().display(),
+                        problem
+                    );
+                    not_restored.push(backup.path().display().to_string());
+                }
+            }
 
-Host *.foo.example.com
-    User Administrator
-"#;
+            let restored = written.len() - not_restored.len();
+            if !not_restored.is_empty() {
+                return problem!(
+                    "Failed to write file {}; restored {} file(s) already written but failed to restore {}; edited files are inconsistent",
+                    file.display(),
+                    restored,
+                    not_restored.join(", ")
+                );
+            }
+            return problem!(
+                "Failed to write file {}; restored {} file(s) already written",
+                file.display(),
+                restored
+            );
+        }
+        written.push(backup);
+    }
 
-    /// Applies edit to input
-    fn pedit(input: &str, args: &[&str]) -> PResult<(String, EditStatus)> {
-        let cli =
-            Cli::from_iter_safe(Some("pedit").iter().chain(args.iter())).or_failed_to("bad args");
-        let args = dbg![cli.edit];
-        let (disp, status) = edit(Cursor::new(input), args)?;
-        let out = disp.to_string();
-        dbg![&status];
-        eprintln!("{}", out);
-        Ok((out, status))
+    // All files are written so on change command runs for each changed file even if it failed for other file
+    for (index, (file, edit)) in pending.iter().enumerate() {
+        let status = edit.outcome.status;
+        info!("{}: {}", file.display(), status);
+        if !status.has_changed() {
+            summary.unchanged += 1;
+        } else if let Err(err) = run_on_change(args, Some(file), &status) {
+            error!("{}: {}", file.display(), err);
+            reports[index].error = Some((&err).into());
+            summary.failed += 1;
+        } else {
+            summary.changed += 1;
+        }
     }
 
-    /// Applies edit to input also verifying that subsequent application on result won't change anything
-    fn stable_pedit(input: &str, args: &[&str]) -> PResult<(String, EditStatus)> {
-        let (output, status) = pedit(input, args)?;
+    Ok(summary)
+}
 
-        // Second run on result should have not changes
-        let (output2, status2) = pedit(&output, args)?;
-        assert!(!status2.has_changed());
-        assert_eq!(output, output2);
+/// Opens input file or standard input of a command that does not edit
+fn query_input(args: &Cli) -> Result<Box<dyn Read>, FatalProblem> {
+    if !args.in_place.is_empty() || !args.recursive.is_empty() {
+        Err(Problem::from_error(
+            "Only --input file or standard input can be read by this command",
+        ))
+        .fatal()?;
+    }
 
-        Ok((output, status))
+    let input = args
+        .input
+        .as_deref()
+        .map(|file| File::open(file).map(|f| Box::new(f) as Box<dyn Read>))
+        .transpose()
+        .problem_while("opening file for reading")
+        .fatal_with_status(EXIT_IO)?
+        .unwrap_or_else(|| Box::new(stdin()) as Box<dyn Read>);
+    let (mut input, _bom) = args
+        .encoding
+        .strip_bom(input)
+        .problem_while("reading input data")
+        .fatal_with_status(EXIT_IO)?;
+
+    if args.encoding != Encoding::Utf8 {
+        let mut input_data = Vec::new();
+        input
+            .read_to_end(&mut input_data)
+            .problem_while("reading input data")
+            .fatal_with_status(EXIT_IO)?;
+        let input_data = args
+            .encoding
+            .decode(input_data)
+            .problem_while("decoding input data")?;
+        input = Box::new(Cursor::new(input_data));
     }
 
-    #[test]
-    fn test_xml_edit() -> FinalResult {
-        let (output, status) = stable_pedit(XML_TEST, &[
-              "line",
-              r#"        <taskbar:DesktopApp DesktopApplicationLinkPath="C:\ProgramData\foo.exe" />"#,
-              "present",
-              "relative-to",
-              "</taskbar:TaskbarPinList>",
-              "before"
-        ])?;
+    Ok(input)
+}
 
-        assert!(status.has_changed());
-        assert_eq!(&output,
+/// Checks if line or pair is present in input file or standard input reading it line by line, exiting with status 8 if it is not
+fn test(args: &Cli, test: &Test) -> FinalResult {
+    let (pattern, continuation) = match test {
+        Test::Line {
+            value,
+            ignore_whitespace,
+            continuation,
+        } => (
+            lines_editor::line_pattern(value, *ignore_whitespace),
+            *continuation,
+        ),
+        Test::LinePair {
+            pair,
+            ignore_whitespace,
+            continuation,
+            separator,
+        } => {
+            let (key, value) = lines_editor::split_pair(pair, separator)
+                .map_err(Problem::from_error)
+                .fatal_with_status(EXIT_INVALID_PAIR_OR_SEPARATOR)?;
+            (
+                lines_editor::pair_pattern(key, value, *ignore_whitespace, separator),
+                *continuation,
+            )
+        }
+    };
+
+    let input = query_input(args)?;
+    let present = lines_editor::contains_match(input, &pattern, continuation)
+        .map_err(|err| EditError::read(err, "reading input text file"))
+        .or_else(EditError::fatal)?;
+
+    if !present {
+        Err(Problem::from_error("Not present (test)")).fatal_with_status(EXIT_NOT_FOUND)?;
+    }
+    Ok(())
+}
+
+/// Prints value of key read from input file or standard input, exiting with status 8 if the key is not found
+fn get(
+    args: &Cli,
+    key: &str,
+    multikey: bool,
+    ignore_whitespace: bool,
+    continuation: bool,
+    separator: &Regex,
+) -> FinalResult {
+    let input = query_input(args)?;
+    let editor = load_lines(input, continuation).or_else(EditError::fatal)?;
+
+    let values = editor
+        .get_values(key, multikey, ignore_whitespace, separator)
+        .or_else(|err| EditError::from(err).fatal())?;
+    if values.is_empty() {
+        Err(Problem::from_error(format!("Key {:?} not found", key)))
+            .fatal_with_status(EXIT_NOT_FOUND)?;
+    }
+
+    for value in values {
+        println!("{}", value);
+    }
+    Ok(())
+}
+
+/// Value of a key with line it was found on as printed by dump command
+#[derive(Debug, Serialize)]
+struct DumpValue {
+    value: String,
+    line: usize,
+}
+
+/// Prints all key and value pairs read from input file or standard input as JSON object with values of each key
+fn dump(
+    args: &Cli,
+    ignore_whitespace: bool,
+    continuation: bool,
+    separator: &Regex,
+    comment: Option<&str>,
+) -> FinalResult {
+    let input = query_input(args)?;
+    let editor = load_lines(input, continuation).or_else(EditError::fatal)?;
+
+    let mut keys: BTreeMap<String, Vec<DumpValue>> = BTreeMap::new();
+    for pair in editor.pairs(ignore_whitespace, separator, comment) {
+        keys.entry(pair.key).or_default().push(DumpValue {
+            value: pair.value,
+            line: pair.line,
+        });
+    }
+
+    println!("{}", serde_json::to_string(&keys)?);
+    Ok(())
+}
+
+//TODO:
+// * stream more edits and placements (e.g. at-top) with --stream
+// * preserve no line ending on last line
+fn main() -> FinalResult {
+    if let Some(args_file) = ansible::args_file(&std::env::args_os().collect::<Vec<_>>()) {
+        return ansible::run(&args_file);
+    }
+
+    let args = Cli::from_args();
+    init_logger(&args.logging, vec![module_path!()]);
+
+    let edit = match &args.command {
+        Command::Edit(edit) => edit,
+        Command::Get {
+            key,
+            multikey,
+            ignore_whitespace,
+            continuation,
+            separator,
+        } => {
+            return get(
+                &args,
+                key,
+                *multikey,
+                *ignore_whitespace,
+                *continuation,
+                separator,
+            )
+        }
+        Command::Test(test) => return self::test(&args, test),
+        Command::Dump {
+            ignore_whitespace,
+            continuation,
+            separator,
+            comment,
+        } => {
+            return dump(
+                &args,
+                *ignore_whitespace,
+                *continuation,
+                separator,
+                comment.as_deref(),
+            )
+        }
+    };
+
+    if args.report.is_some()
+        && !args.check
+        && args.output.is_none()
+        && args.in_place.is_empty()
+        && args.recursive.is_empty()
+    {
+        Err(Problem::from_error(
+            "Report can not be printed to standard output together with edited content; use --output or --check",
+        ))
+        .fatal()?;
+    }
+    args.new_file().check()?;
+
+    let mut files = files::expand_patterns(&args.in_place)?;
+    for dir in &args.recursive {
+        files.extend(files::walk(dir, &args.include, &args.exclude)?);
+    }
+    let files = files::unique(files);
+
+    let mut reports = Vec::new();
+
+    let result = match files.as_slice() {
+        [] if args.in_place.is_empty() && args.recursive.is_empty() => {
+            let result = edit_file(
+                &args,
+                edit,
+                args.input.as_deref(),
+                args.output.as_deref(),
+                false,
+            );
+            reports.push(FileReport::new(args.input.as_deref(), result.as_ref()));
+            result
+                .map(|outcome| outcome.status.has_changed())
+                .map_err(Problem::from)
+        }
+        [file] if args.recursive.is_empty() => {
+            let result = edit_file(&args, edit, Some(file), Some(file), false);
+            reports.push(FileReport::new(Some(file), result.as_ref()));
+            result
+                .map(|outcome| outcome.status.has_changed())
+                .problem_while_with(|| format!("editing file {}", file.display()))
+        }
+        files => if args.transactional {
+            edit_files_transactional(&args, edit, files, &mut reports)
+        } else {
+            Ok(edit_files(&args, edit, files, &mut reports))
+        }
+        .and_then(|summary| {
+            info!("{}", summary);
+
+            if summary.failed > 0 {
+                problem!("Failed to edit {} file(s)", summary.failed)
+            } else {
+                Ok(summary.changed > 0)
+            }
+        }),
+    };
+
+    print_report(&args, edit, reports.as_slice(), result.as_ref().err())?;
+
+    let exit_status = reports
+        .iter()
+        .find_map(|report| report.error.as_ref())
+        .map_or(EXIT_FAILURE, |error| error.exit_status);
+    let changed = result.fatal_with_status(exit_status)?;
+
+    if args.check && changed {
+        Err(Problem::from_error("File would have changed (check)"))
+            .fatal_with_status(EXIT_CHANGED)?;
+    }
+    if args.detailed_exit_codes && changed {
+        stdout().flush()?;
+        std::process::exit(EXIT_CHANGED);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML_TEST: &str =
+r#"<LayoutModificationTemplate
+    xmlns="http://schemas.microsoft.com/Start/2014/LayoutModification"
+    xmlns:defaultlayout="http://schemas.microsoft.com/Start/2014/FullDefaultLayout"
+    xmlns:start="http://schemas.microsoft.com/Start/2014/StartLayout"
+    xmlns:taskbar="http://schemas.microsoft.com/Start/2014/TaskbarLayout"
+    Version="1">
+  <CustomTaskbarLayoutCollection PinListPlacement="Replace">
+    <defaultlayout:TaskbarLayout>
+      <taskbar:TaskbarPinList>
+        <taskbar:DesktopApp DesktopApplicationLinkPath="C:\Users\Administrator\AppData\Roaming\Microsoft\Windows\Start Menu\Programs\Scoop Apps\Process Explorer.lnk" />
+        <taskbar:DesktopApp DesktopApplicationLinkPath="C:\ProgramData\Microsoft\Windows\Start Menu\Programs\Administrative Tools\IIS Manager.lnk" />
+      </taskbar:TaskbarPinList>
+    </defaultlayout:TaskbarLayout>
+  </CustomTaskbarLayoutCollection>
+</LayoutModificationTemplate>"#;
+
+    pub const SSH_TEST: &str = r#"UserKnownHostsFile /dev/null
+StrictHostKeyChecking no
+IdentityFile ~/.ssh/foo
+IdentityFile ~/.ssh/bar
+
+Host *.foo.example.com
+    User Administrator
+"#;
+
+    const GIT_CONFIG_TEST: &str = r#"[core]
+	bare = false
+	autocrlf
+[Remote "origin"]
+	url = git@example.com:foo/bar.git
+	fetch = +refs/heads/*:refs/remotes/origin/*
+
+[branch "master"]
+	remote = origin
+	merge = refs/heads/master
+"#;
+
+    const FSTAB_TEST: &str = r#"# <file system>  <mount point>  <type>  <options>         <dump>  <pass>
+UUID=1234        /              ext4    errors=remount-ro 0       1
+UUID=5678        /home          ext4    defaults          0       2
+tmpfs            /tmp           tmpfs   nosuid,nodev      0       0
+"#;
+
+    /// Applies edit to input
+    pub fn pedit(input: &str, args: &[&str]) -> PResult<(String, EditStatus)> {
+        let cli =
+            Cli::from_iter_safe(Some("pedit").iter().chain(args.iter())).or_failed_to("bad args");
+        let args = match cli.command {
+            Command::Edit(edit) => dbg![edit],
+            command => panic!("not an edit: {:?}", command),
+        };
+        let (disp, status) = edit(Cursor::new(input), args)?;
+        let out = disp.to_string();
+        dbg![&status];
+        eprintln!("{}", out);
+        Ok((out, status))
+    }
+
+    /// Applies edit to input also verifying that subsequent application on result won't change anything
+    fn stable_pedit(input: &str, args: &[&str]) -> PResult<(String, EditStatus)> {
+        let (output, status) = pedit(input, args)?;
+
+        // Second run on result should have not changes
+        let (output2, status2) = pedit(&output, args)?;
+        assert!(!status2.has_changed());
+        assert_eq!(output, output2);
+
+        Ok((output, status))
+    }
+
+    #[test]
+    fn test_xml_edit() -> FinalResult {
+        let (output, status) = stable_pedit(XML_TEST, &[
+              "line",
+              r#"        <taskbar:DesktopApp DesktopApplicationLinkPath="C:\ProgramData\foo.exe" />"#,
+              "present",
+              "relative-to",
+              "</taskbar:TaskbarPinList>",
+              "before"
+        ])?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output,
 r#"<LayoutModificationTemplate
     xmlns="http://schemas.microsoft.com/Start/2014/LayoutModification"
     xmlns:defaultlayout="http://schemas.microsoft.com/Start/2014/FullDefaultLayout"
@@ -250,14 +1160,563 @@ r#"<LayoutModificationTemplate
     }
 
     #[test]
-    fn test_ssh_edit_key_value() -> FinalResult {
+    fn test_ssh_edit_key_value() -> FinalResult {
+        let (output, status) = stable_pedit(
+            SSH_TEST,
+            &[
+                "line-pair",
+                "-s",
+                " ",
+                r#"StrictHostKeyChecking yes"#,
+                "present",
+                "at-end",
+            ],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(
+            &output,
+            r#"UserKnownHostsFile /dev/null
+StrictHostKeyChecking yes
+IdentityFile ~/.ssh/foo
+IdentityFile ~/.ssh/bar
+
+Host *.foo.example.com
+    User Administrator
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ssh_edit_multikey_value() -> FinalResult {
+        let (output, status) = stable_pedit(
+            SSH_TEST,
+            &[
+                "line-pair",
+                "-s",
+                " ",
+                "-m",
+                r#"IdentityFile ~/.ssh/quix"#,
+                "present",
+                "at-top",
+            ],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(
+            &output,
+            r#"IdentityFile ~/.ssh/quix
+UserKnownHostsFile /dev/null
+StrictHostKeyChecking no
+IdentityFile ~/.ssh/foo
+IdentityFile ~/.ssh/bar
+
+Host *.foo.example.com
+    User Administrator
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ssh_edit_key_multi_candind() {
+        let err = stable_pedit(
+            SSH_TEST,
+            &[
+                "line-pair",
+                "-s",
+                " ",
+                r#"IdentityFile ~/.ssh/quix"#,
+                "present",
+                "at-top",
+            ],
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("Multiple candidates found"));
+    }
+
+    #[test]
+    fn test_edit_pair_value() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "foo = 1\nbar = 2\nbaz = 3",
+            &["line-pair", "bar = 4", "present", "at-top"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "foo = 1\nbar = 4\nbaz = 3\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_pair_multikey_value() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "foo = 1\nbar = 2\nbaz = 3",
+            &["line-pair", "-m", "bar = 4", "present", "at-top"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "bar = 4\nfoo = 1\nbar = 2\nbaz = 3\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_pair_multiple_candidates() {
+        let err = stable_pedit(
+            "foo = 1\nbar = 2\nbar = 3\nbaz = 3",
+            &["line-pair", "bar = 4", "present", "at-top"],
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().starts_with("Multiple candidates found"));
+    }
+
+    #[test]
+    fn test_edit_line_pair_relative_to_before_middle() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "foo = 1\nbar = 2\nbaz = 3",
+            &[
+                "line",
+                "quix = 4",
+                "present",
+                "relative-to",
+                "bar",
+                "before",
+            ],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "foo = 1\nquix = 4\nbar = 2\nbaz = 3\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_line_relative_to_before_top() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "foo\nbar\nbaz",
+            &["line", "quix", "present", "relative-to", "foo", "before"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "quix\nfoo\nbar\nbaz\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_line_relative_to_before_middle() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "foo\nbar\nbaz",
+            &["line", "quix", "present", "relative-to", "bar", "before"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "foo\nquix\nbar\nbaz\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_line_relative_to_before_end() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "foo\nbar\nbaz",
+            &["line", "quix", "present", "relative-to", "baz", "before"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "foo\nbar\nquix\nbaz\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_line_relative_to_after_top() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "foo\nbar\nbaz",
+            &["line", "quix", "present", "relative-to", "foo", "after"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "foo\nquix\nbar\nbaz\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_line_relative_to_after_middle() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "foo\nbar\nbaz",
+            &["line", "quix", "present", "relative-to", "bar", "after"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "foo\nbar\nquix\nbaz\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_line_relative_to_after_end() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "foo\nbar\nbaz",
+            &["line", "quix", "present", "relative-to", "baz", "after"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "foo\nbar\nbaz\nquix\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_line_relative_to_multiple_candidates() {
+        let err = stable_pedit(
+            "foo\nfoo",
+            &["line", r#"bar"#, "present", "relative-to", "foo", "before"],
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().starts_with("Multiple candidates found"));
+    }
+
+    #[test]
+    fn test_edit_line_absent_middle() -> FinalResult {
+        let (output, status) = stable_pedit("foo\nbar\nbaz", &["line", "bar", "absent"])?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "foo\nbaz\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_line_absent_top() -> FinalResult {
+        let (output, status) = stable_pedit("foo\nbar\nbaz", &["line", "foo", "absent"])?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "bar\nbaz\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_line_absent_end() -> FinalResult {
+        let (output, status) = stable_pedit("foo\nbar\nbaz", &["line", "baz", "absent"])?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "foo\nbar\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_line_absent_multiple_candidates() {
+        let err = stable_pedit("foo\nbaz\nbaz", &["line", "baz", "absent"]).unwrap_err();
+
+        assert!(err.to_string().starts_with("Multiple candidates found"));
+    }
+
+    #[test]
+    fn test_edit_line_pair_absent_middle() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "foo = 1\nbar = 2\nbaz = 3",
+            &["line-pair", "bar = 2", "absent"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "foo = 1\nbaz = 3\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_line_pair_absent_multiple_candidates() {
+        let err = stable_pedit(
+            "foo = 1\nbaz = 2\nbaz = 2",
+            &["line-pair", "baz = 2", "absent"],
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().starts_with("Multiple candidates found"));
+    }
+
+    #[test]
+    fn test_git_config_replace_key_in_subsection() -> FinalResult {
+        let (output, status) = stable_pedit(
+            GIT_CONFIG_TEST,
+            &[
+                "git-config",
+                "remote.origin.URL",
+                "https://example.com/foo/bar.git",
+                "present",
+                "at-end",
+            ],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(
+            &output,
+            r#"[core]
+	bare = false
+	autocrlf
+[Remote "origin"]
+	url = https://example.com/foo/bar.git
+	fetch = +refs/heads/*:refs/remotes/origin/*
+
+[branch "master"]
+	remote = origin
+	merge = refs/heads/master
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_config_boolean_key_already_present() -> FinalResult {
+        let (output, status) = stable_pedit(
+            GIT_CONFIG_TEST,
+            &["git-config", "core.autocrlf", "true", "present", "at-end"],
+        )?;
+
+        assert!(!status.has_changed());
+        assert_eq!(&output, GIT_CONFIG_TEST);
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_config_multikey_present() -> FinalResult {
+        let (output, status) = stable_pedit(
+            GIT_CONFIG_TEST,
+            &[
+                "git-config",
+                "-m",
+                "remote.origin.fetch",
+                "+refs/tags/*:refs/tags/*",
+                "present",
+                "at-end",
+            ],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(
+            &output,
+            r#"[core]
+	bare = false
+	autocrlf
+[Remote "origin"]
+	url = git@example.com:foo/bar.git
+	fetch = +refs/heads/*:refs/remotes/origin/*
+	fetch = +refs/tags/*:refs/tags/*
+
+[branch "master"]
+	remote = origin
+	merge = refs/heads/master
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_config_new_section_quoted_value() -> FinalResult {
+        let (output, status) = stable_pedit(
+            GIT_CONFIG_TEST,
+            &[
+                "git-config",
+                "alias.lg",
+                "log --graph # pretty",
+                "present",
+                "at-end",
+            ],
+        )?;
+
+        assert!(status.has_changed());
+        assert!(output.ends_with("[alias]\n\tlg = \"log --graph # pretty\"\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_config_absent_removes_empty_section() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "[user]\n\tname = Foo\n\n[alias]\n\tco = checkout\n",
+            &["git-config", "alias.co", "checkout", "absent"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "[user]\n\tname = Foo\n\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_config_entry_on_header_line() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "[core] bare = true\n\tautoCRLF = true\n",
+            &["git-config", "core.bare", "false", "present", "at-end"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "[core] bare = false\n\tautoCRLF = true\n");
+
+        let (output, status) = stable_pedit(
+            "[core] bare = true\n\tautoCRLF = true\n",
+            &["git-config", "core.autocrlf", "input", "present", "at-end"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "[core] bare = true\n\tautoCRLF = input\n");
+
+        let (output, status) = stable_pedit(
+            "[core] bare = true\n",
+            &["git-config", "core.bare", "true", "absent"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_config_multiple_candidates() {
+        let err = stable_pedit(
+            "[remote \"origin\"]\n\tfetch = a\n\tfetch = b\n",
+            &["git-config", "remote.origin.fetch", "c", "present", "at-end"],
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().starts_with("Multiple candidates found"));
+    }
+
+    #[test]
+    fn test_properties_replace_continued_value() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "# app\nfoo = 1\nlist = a,\\\n       b,\\\n       c\nbar: 2\n",
+            &["properties", "list", "a,b", "present", "at-end"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "# app\nfoo = 1\nlist = a,b\nbar: 2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_properties_escaped_key_already_present() -> FinalResult {
+        let input = "a\\=b\\:c = caf\\u00e9\nwith\\ space value\n";
+        let (output, status) = stable_pedit(
+            input,
+            &["properties", "a=b:c", "café", "present", "at-end"],
+        )?;
+        assert!(!status.has_changed());
+        assert_eq!(&output, input);
+
+        let (output, status) = stable_pedit(
+            input,
+            &["properties", "with space", "value", "present", "at-end"],
+        )?;
+        assert!(!status.has_changed());
+        assert_eq!(&output, input);
+        Ok(())
+    }
+
+    #[test]
+    fn test_properties_insert_escaped() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "foo: 1\n",
+            &["properties", "-u", "a key=", " café", "present", "at-top"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "a\\ key\\=: \\ caf\\u00E9\nfoo: 1\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_properties_absent_removes_all_physical_lines() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "foo=1\nbar=x\\\n  y\nbaz=3\n",
+            &["properties", "bar", "xy", "absent"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "foo=1\nbaz=3\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_quoted_value_already_present() -> FinalResult {
+        let input = "# defaults\nexport FOO=\"bar\"\nBAZ='a b'\n";
+        let (output, status) =
+            stable_pedit(input, &["env", "FOO", "bar", "present", "at-end"])?;
+        assert!(!status.has_changed());
+        assert_eq!(&output, input);
+
+        let (output, status) =
+            stable_pedit(input, &["env", "BAZ", "a b", "present", "at-end"])?;
+        assert!(!status.has_changed());
+        assert_eq!(&output, input);
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_replace_keeps_export() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "export FOO=bar\nBAZ=1\n",
+            &["env", "FOO", "some $value", "present", "at-end"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "export FOO=\"some \\$value\"\nBAZ=1\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_replace_keeps_comment() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "FOO=\"bar\"  # comment\n",
+            &["env", "FOO", "baz", "present", "at-end"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "FOO=baz  # comment\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_insert_unquoted() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "FOO=bar\n",
+            &["env", "-e", "PATH", "/usr/bin:/bin", "present", "at-end"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "FOO=bar\nexport PATH=/usr/bin:/bin\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_absent() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "FOO=\"bar\"\nBAZ=1\n",
+            &["env", "FOO", "bar", "absent"],
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "BAZ=1\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_columns_replace_preserves_alignment() -> FinalResult {
         let (output, status) = stable_pedit(
-            SSH_TEST,
+            FSTAB_TEST,
             &[
-                "line-pair",
-                "-s",
-                " ",
-                r#"StrictHostKeyChecking yes"#,
+                "columns",
+                "-k",
+                "2",
+                "-V",
+                "4",
+                "UUID=5678 /home ext4 defaults,noatime 0 2",
                 "present",
                 "at-end",
             ],
@@ -266,270 +1725,547 @@ r#"<LayoutModificationTemplate
         assert!(status.has_changed());
         assert_eq!(
             &output,
-            r#"UserKnownHostsFile /dev/null
-StrictHostKeyChecking yes
-IdentityFile ~/.ssh/foo
-IdentityFile ~/.ssh/bar
-
-Host *.foo.example.com
-    User Administrator
+            r#"# <file system>  <mount point>  <type>  <options>         <dump>  <pass>
+UUID=1234        /              ext4    errors=remount-ro 0       1
+UUID=5678        /home          ext4    defaults,noatime  0       2
+tmpfs            /tmp           tmpfs   nosuid,nodev      0       0
 "#
         );
         Ok(())
     }
 
     #[test]
-    fn test_ssh_edit_multikey_value() -> FinalResult {
+    fn test_columns_insert_new_record() -> FinalResult {
         let (output, status) = stable_pedit(
-            SSH_TEST,
+            FSTAB_TEST,
             &[
-                "line-pair",
-                "-s",
-                " ",
-                "-m",
-                r#"IdentityFile ~/.ssh/quix"#,
+                "columns",
+                "-k",
+                "2",
+                "UUID=9abc /srv xfs defaults 0 2",
                 "present",
-                "at-top",
+                "at-end",
             ],
         )?;
 
+        assert!(status.has_changed());
+        assert!(output.ends_with("nosuid,nodev      0       0\nUUID=9abc /srv xfs defaults 0 2\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_columns_skip_comment() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "#UUID=9abc /srv xfs defaults 0 2\n",
+            &["columns", "-k", "2", "UUID=9abc /srv ext4 defaults 0 2", "present", "at-end"],
+        )?;
+
         assert!(status.has_changed());
         assert_eq!(
             &output,
-            r#"IdentityFile ~/.ssh/quix
-UserKnownHostsFile /dev/null
-StrictHostKeyChecking no
-IdentityFile ~/.ssh/foo
-IdentityFile ~/.ssh/bar
-
-Host *.foo.example.com
-    User Administrator
-"#
+            "#UUID=9abc /srv xfs defaults 0 2\nUUID=9abc /srv ext4 defaults 0 2\n"
         );
         Ok(())
     }
 
     #[test]
-    fn test_ssh_edit_key_multi_candind() {
-        let err = stable_pedit(
-            SSH_TEST,
+    fn test_columns_already_present() -> FinalResult {
+        let (output, status) = stable_pedit(
+            FSTAB_TEST,
+            &["columns", "-k", "2", "-V", "3", "x /tmp tmpfs", "present", "at-end"],
+        )?;
+
+        assert!(!status.has_changed());
+        assert_eq!(&output, FSTAB_TEST);
+        Ok(())
+    }
+
+    #[test]
+    fn test_columns_extend_short_line() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "UUID=1234 / ext4\n",
             &[
-                "line-pair",
-                "-s",
-                " ",
-                r#"IdentityFile ~/.ssh/quix"#,
+                "columns",
+                "-k",
+                "2",
+                "-V",
+                "4",
+                "UUID=1234 / ext4 defaults 0 1",
                 "present",
-                "at-top",
+                "at-end",
             ],
-        )
-        .unwrap_err();
-        assert_eq!(&err.to_string(), "Multiple candidates found");
+        )?;
+
+        assert!(status.has_changed());
+        assert_eq!(&output, "UUID=1234 / ext4 defaults\n");
+        Ok(())
     }
 
     #[test]
-    fn test_edit_pair_value() -> FinalResult {
+    fn test_columns_absent() -> FinalResult {
         let (output, status) = stable_pedit(
-            "foo = 1\nbar = 2\nbaz = 3",
-            &["line-pair", "bar = 4", "present", "at-top"],
+            "127.0.0.1\tlocalhost\n10.0.0.1\tfoo foo.example.com\n",
+            &["columns", "-k", "2", "-V", "1", "10.0.0.1 foo", "absent"],
         )?;
 
         assert!(status.has_changed());
-        assert_eq!(&output, "foo = 1\nbar = 4\nbaz = 3\n");
-
+        assert_eq!(&output, "127.0.0.1\tlocalhost\n");
         Ok(())
     }
 
     #[test]
-    fn test_edit_pair_multikey_value() -> FinalResult {
+    fn test_columns_absent_by_key() -> FinalResult {
         let (output, status) = stable_pedit(
-            "foo = 1\nbar = 2\nbaz = 3",
-            &["line-pair", "-m", "bar = 4", "present", "at-top"],
+            FSTAB_TEST,
+            &["columns", "-k", "2", "x /tmp", "absent"],
         )?;
 
         assert!(status.has_changed());
-        assert_eq!(&output, "bar = 4\nfoo = 1\nbar = 2\nbaz = 3\n");
+        assert!(!output.contains("/tmp"));
 
+        let (output, status) = stable_pedit(
+            FSTAB_TEST,
+            &["columns", "-k", "2", "-V", "3", "x /tmp ext4", "absent"],
+        )?;
+
+        assert!(!status.has_changed());
+        assert_eq!(&output, FSTAB_TEST);
         Ok(())
     }
 
     #[test]
-    fn test_edit_pair_multiple_candidates() {
-        let err = stable_pedit(
-            "foo = 1\nbar = 2\nbar = 3\nbaz = 3",
-            &["line-pair", "bar = 4", "present", "at-top"],
-        )
-        .unwrap_err();
+    fn test_list_item_present() -> FinalResult {
+        let (output, status) = stable_pedit(
+            "Port 22\nAllowUsers alice bob\n",
+            &["list-item", "-s", " ", "AllowUsers carol", "present", "at-end"],
+        )?;
 
-        assert_eq!(&err.to_string(), "Multiple candidates found");
+        assert!(status.has_changed());
+        assert_eq!(&output, "Port 22\nAllowUsers alice bob carol\n");
+        Ok(())
     }
 
     #[test]
-    fn test_edit_line_pair_relative_to_before_middle() -> FinalResult {
+    fn test_list_item_quoted_present() -> FinalResult {
         let (output, status) = stable_pedit(
-            "foo = 1\nbar = 2\nbaz = 3",
-            &[
-                "line",
-                "quix = 4",
-                "present",
-                "relative-to",
-                "bar",
-                "before",
-            ],
+            "GRUB_TIMEOUT=5\nGRUB_CMDLINE_LINUX=\"quiet splash\"\n",
+            &["list-item", "-q", "\"", "GRUB_CMDLINE_LINUX=nomodeset", "present", "at-end"],
         )?;
 
         assert!(status.has_changed());
-        assert_eq!(&output, "foo = 1\nquix = 4\nbar = 2\nbaz = 3\n");
-
+        assert_eq!(
+            &output,
+            "GRUB_TIMEOUT=5\nGRUB_CMDLINE_LINUX=\"quiet splash nomodeset\"\n"
+        );
         Ok(())
     }
 
     #[test]
-    fn test_edit_line_relative_to_before_top() -> FinalResult {
+    fn test_list_item_keeps_quoting() -> FinalResult {
         let (output, status) = stable_pedit(
-            "foo\nbar\nbaz",
-            &["line", "quix", "present", "relative-to", "foo", "before"],
+            "AllowUsers alice\n",
+            &["list-item", "-s", " ", "-q", "\"", "AllowUsers bob", "present", "at-end"],
         )?;
 
         assert!(status.has_changed());
-        assert_eq!(&output, "quix\nfoo\nbar\nbaz\n");
-
+        assert_eq!(&output, "AllowUsers alice bob\n");
         Ok(())
     }
 
     #[test]
-    fn test_edit_line_relative_to_before_middle() -> FinalResult {
+    fn test_list_item_absent_last_item() -> FinalResult {
         let (output, status) = stable_pedit(
-            "foo\nbar\nbaz",
-            &["line", "quix", "present", "relative-to", "bar", "before"],
+            "X=\"a\"\nY=1\n",
+            &["list-item", "-q", "\"", "X=a", "absent"],
         )?;
 
         assert!(status.has_changed());
-        assert_eq!(&output, "foo\nquix\nbar\nbaz\n");
-
+        assert_eq!(&output, "Y=1\n");
         Ok(())
     }
 
     #[test]
-    fn test_edit_line_relative_to_before_end() -> FinalResult {
+    fn test_list_item_absent() -> FinalResult {
         let (output, status) = stable_pedit(
-            "foo\nbar\nbaz",
-            &["line", "quix", "present", "relative-to", "baz", "before"],
+            "PATH=/a:/b:/c\n",
+            &["list-item", "-d", ":", "PATH=/b", "absent"],
         )?;
 
         assert!(status.has_changed());
-        assert_eq!(&output, "foo\nbar\nquix\nbaz\n");
+        assert_eq!(&output, "PATH=/a:/c\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_item_keeps_other_items() -> FinalResult {
+        let (output, _) = stable_pedit(
+            "PATH=/a::/b\n",
+            &["list-item", "-d", ":", "PATH=/c", "present", "at-end"],
+        )?;
+        assert_eq!(&output, "PATH=/a::/b:/c\n");
+
+        let (output, _) = stable_pedit(
+            "AllowUsers alice  bob\n",
+            &["list-item", "-s", " ", "AllowUsers carol", "present", "at-end"],
+        )?;
+        assert_eq!(&output, "AllowUsers alice  bob carol\n");
 
+        let (output, status) = stable_pedit(
+            "AllowUsers alice  bob\n",
+            &["list-item", "-s", " ", "AllowUsers alice", "present", "at-end"],
+        )?;
+        assert!(!status.has_changed());
+        assert_eq!(&output, "AllowUsers alice  bob\n");
         Ok(())
     }
 
     #[test]
-    fn test_edit_line_relative_to_after_top() -> FinalResult {
+    fn test_list_item_missing_key() -> FinalResult {
         let (output, status) = stable_pedit(
-            "foo\nbar\nbaz",
-            &["line", "quix", "present", "relative-to", "foo", "after"],
+            "Port 22\n",
+            &["list-item", "-s", " ", "AllowUsers alice", "present", "at-end"],
         )?;
 
         assert!(status.has_changed());
-        assert_eq!(&output, "foo\nquix\nbar\nbaz\n");
-
+        assert_eq!(&output, "Port 22\nAllowUsers alice\n");
         Ok(())
     }
 
     #[test]
-    fn test_edit_line_relative_to_after_middle() -> FinalResult {
+    fn test_edit_pair_continuation_replace() -> FinalResult {
         let (output, status) = stable_pedit(
-            "foo\nbar\nbaz",
-            &["line", "quix", "present", "relative-to", "bar", "after"],
+            "CFLAGS = -O2 \\\n\t-Wall\nLDFLAGS = -lm\n",
+            &["line-pair", "-l", "CFLAGS = -O3", "present", "at-end"],
         )?;
 
         assert!(status.has_changed());
-        assert_eq!(&output, "foo\nbar\nquix\nbaz\n");
-
+        assert_eq!(&output, "CFLAGS = -O3\nLDFLAGS = -lm\n");
         Ok(())
     }
 
     #[test]
-    fn test_edit_line_relative_to_after_end() -> FinalResult {
+    fn test_edit_line_continuation_absent() -> FinalResult {
         let (output, status) = stable_pedit(
-            "foo\nbar\nbaz",
-            &["line", "quix", "present", "relative-to", "baz", "after"],
+            "foo\nbar \\\nbaz\nquix\n",
+            &["line", "-l", "bar baz", "absent"],
         )?;
 
         assert!(status.has_changed());
-        assert_eq!(&output, "foo\nbar\nbaz\nquix\n");
+        assert_eq!(&output, "foo\nquix\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_line_continuation_already_present() -> FinalResult {
+        let input = "foo \\\nbar\n";
+        let (output, status) = stable_pedit(
+            input,
+            &["line", "-l", "foo bar", "present", "at-end"],
+        )?;
 
+        assert!(!status.has_changed());
+        assert_eq!(&output, input);
         Ok(())
     }
 
     #[test]
-    fn test_edit_line_relative_to_multiple_candidates() {
-        let err = stable_pedit(
-            "foo\nfoo",
-            &["line", r#"bar"#, "present", "relative-to", "foo", "before"],
-        )
-        .unwrap_err();
+    fn test_file_report_json() -> FinalResult {
+        let outcome = Outcome {
+            status: EditStatus::Present(editor::PresentStatus::InsertedPlacement),
+            changed_lines: ChangedLines::new("foo\n", "foo\nbar\n"),
+        };
+        let report = FileReport::new(Some(Path::new("hosts")), Ok(&outcome));
+        assert_eq!(
+            serde_json::to_string(&report)?,
+            r#"{"file":"hosts","status":"InsertedPlacement","changed":true,"changed_lines":{"removed":[],"added":[2]},"error":null}"#
+        );
 
-        assert_eq!(&err.to_string(), "Multiple candidates found");
+        let err = EditError::Editor(LinesEditorError::MultipleCandidates);
+        let report = FileReport::new(None, Err(&err));
+        assert_eq!(
+            serde_json::to_string(&report)?,
+            r#"{"file":null,"status":null,"changed":false,"changed_lines":{"removed":[],"added":[]},"error":{"kind":"MultipleCandidates","message":"Multiple candidates found"}}"#
+        );
+        Ok(())
     }
 
     #[test]
-    fn test_edit_line_absent_middle() -> FinalResult {
-        let (output, status) = stable_pedit("foo\nbar\nbaz", &["line", "bar", "absent"])?;
+    fn test_exit_status() {
+        let exit_status = |args: &[&str]| {
+            let cli = Cli::from_iter_safe(Some("pedit").iter().chain(args.iter())).unwrap();
+            let args = match cli.command {
+                Command::Edit(edit) => edit,
+                command => panic!("not an edit: {:?}", command),
+            };
+            edit(Cursor::new(SSH_TEST), args)
+                .err()
+                .map(|err| err.exit_status())
+        };
 
-        assert!(status.has_changed());
-        assert_eq!(&output, "foo\nbaz\n");
+        assert_eq!(
+            exit_status(&["line-pair", "-s", " ", "IdentityFile ~/.ssh/baz", "present", "at-end"]),
+            Some(EXIT_MULTIPLE_CANDIDATES)
+        );
+        assert_eq!(
+            exit_status(&["line", "foo", "present", "relative-to", "^bar", "before"]),
+            Some(EXIT_NOT_APPLICABLE)
+        );
+        assert_eq!(
+            exit_status(&["line-pair", "foo", "present", "at-end"]),
+            Some(EXIT_INVALID_PAIR_OR_SEPARATOR)
+        );
+        assert_eq!(
+            exit_status(&["env", "1FOO", "bar", "present", "at-end"]),
+            Some(EXIT_INVALID_KEY)
+        );
 
-        Ok(())
+        let cli = Cli::from_iter_safe(&["pedit", "line", "foo", "absent"]).unwrap();
+        let line = match cli.command {
+            Command::Edit(edit) => edit,
+            command => panic!("not an edit: {:?}", command),
+        };
+        let err = edit(Cursor::new(b"Fran\xe7ois\n".to_vec()), line)
+            .err()
+            .unwrap();
+        assert_eq!(err.exit_status(), EXIT_FAILURE);
+    }
+
+    /// Applies edit to file with given content through the same path as in place edits returning new file content
+    fn pedit_file(name: &str, content: &[u8], args: &[&str]) -> PResult<Vec<u8>> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join(name);
+        fs::write(&file, content)?;
+
+        let cli = Cli::from_iter_safe(Some("pedit").iter().chain(args.iter()))?;
+        let edit = match &cli.command {
+            Command::Edit(edit) => edit,
+            command => panic!("not an edit: {:?}", command),
+        };
+        let pending = prepare_edit(&cli, edit, Some(&file), Some(&file))?;
+        write_edit(&cli, Some(&file), Some(&file), &pending)?;
+        let content = fs::read(&file)?;
+        Ok(content)
     }
 
     #[test]
-    fn test_edit_line_absent_top() -> FinalResult {
-        let (output, status) = stable_pedit("foo\nbar\nbaz", &["line", "foo", "absent"])?;
+    fn test_latin1_round_trip() -> FinalResult {
+        let content = pedit_file(
+            "latin1",
+            b"name=Fran\xe7ois\ncity=K\xf6ln\n",
+            &[
+                "--encoding",
+                "latin1",
+                "line-pair",
+                "city=M\u{fc}nchen",
+                "present",
+                "at-end",
+            ],
+        )?;
+        assert_eq!(content, b"name=Fran\xe7ois\ncity=M\xfcnchen\n");
+        assert!(Encoding::Latin1.encode("\u{20ac}", false).is_err());
+        Ok(())
+    }
 
-        assert!(status.has_changed());
-        assert_eq!(&output, "bar\nbaz\n");
+    #[test]
+    fn test_bom_preserved() -> FinalResult {
+        let content = pedit_file(
+            "bom-top",
+            b"\xef\xbb\xbf[core]\n",
+            &["line", "# managed", "present", "at-top"],
+        )?;
+        assert_eq!(content, b"\xef\xbb\xbf# managed\n[core]\n");
 
+        let content = pedit_file(
+            "bom-anchor",
+            b"\xef\xbb\xbf[core]\n",
+            &[
+                "line",
+                "; core",
+                "present",
+                "relative-to",
+                "^\\[core\\]$",
+                "before",
+            ],
+        )?;
+        assert_eq!(content, b"\xef\xbb\xbf; core\n[core]\n");
         Ok(())
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_edit_line_absent_end() -> FinalResult {
-        let (output, status) = stable_pedit("foo\nbar\nbaz", &["line", "baz", "absent"])?;
+    fn test_unchanged_file_not_replaced() -> FinalResult {
+        use std::os::unix::fs::MetadataExt;
 
-        assert!(status.has_changed());
-        assert_eq!(&output, "foo\nbar\n");
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("hosts");
+        fs::write(&file, "127.0.0.1 localhost\n")?;
+        let inode = fs::metadata(&file)?.ino();
 
+        let cli =
+            Cli::from_iter_safe(&["pedit", "line", "127.0.0.1 localhost", "present", "at-end"])?;
+        let edit = match &cli.command {
+            Command::Edit(edit) => edit,
+            command => panic!("not an edit: {:?}", command),
+        };
+        let outcome = edit_file(&cli, edit, Some(&file), Some(&file), false)?;
+        assert!(!outcome.status.has_changed());
+        assert_eq!(fs::metadata(&file)?.ino(), inode);
         Ok(())
     }
 
     #[test]
-    fn test_edit_line_absent_multiple_candidates() {
-        let err = stable_pedit("foo\nbaz\nbaz", &["line", "baz", "absent"]).unwrap_err();
-
-        assert_eq!(&err.to_string(), "Multiple candidates found");
+    fn test_create_only_missing() -> FinalResult {
+        let dir = tempfile::tempdir()?;
+        let plain = dir.path().join("plain");
+        fs::write(&plain, "")?;
+
+        let cli = Cli::from_iter_safe(&["pedit", "--create", "line", "foo", "present", "at-end"])?;
+        let edit = match &cli.command {
+            Command::Edit(edit) => edit,
+            command => panic!("not an edit: {:?}", command),
+        };
+
+        let missing = dir.path().join("missing.conf");
+        let pending = prepare_edit(&cli, edit, Some(&missing), Some(&missing))?;
+        assert_eq!(pending.output_data, "foo\n");
+
+        // Failing to open file for other reason than it missing is an error
+        let not_dir = plain.join("child.conf");
+        assert!(prepare_edit(&cli, edit, Some(&not_dir), Some(&not_dir)).is_err());
+        Ok(())
     }
 
     #[test]
-    fn test_edit_line_pair_absent_middle() -> FinalResult {
+    fn test_pair_ambiguous_anchor() -> FinalResult {
+        // Existing key is replaced without looking for anchor
         let (output, status) = stable_pedit(
-            "foo = 1\nbar = 2\nbaz = 3",
-            &["line-pair", "bar = 2", "absent"],
+            SSH_TEST,
+            &[
+                "line-pair",
+                "-s",
+                " ",
+                "StrictHostKeyChecking yes",
+                "present",
+                "relative-to",
+                "^IdentityFile",
+                "before",
+            ],
         )?;
+        assert!(matches!(status, EditStatus::Replaced(editor::ReplaceStatus::Replaced)));
+        assert!(output.contains("StrictHostKeyChecking yes\nIdentityFile"));
 
-        assert!(status.has_changed());
-        assert_eq!(&output, "foo = 1\nbaz = 3\n");
+        let err = pedit(
+            SSH_TEST,
+            &[
+                "line-pair",
+                "-s",
+                " ",
+                "Compression yes",
+                "present",
+                "relative-to",
+                "^IdentityFile",
+                "before",
+            ],
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Multiple candidates found"));
+        Ok(())
+    }
+
+    fn get_values(input: &str, args: &[&str]) -> Result<Vec<String>, LinesEditorError> {
+        let cli = Cli::from_iter_safe(Some("pedit").iter().chain(args.iter())).unwrap();
+        match cli.command {
+            Command::Get {
+                key,
+                multikey,
+                ignore_whitespace,
+                continuation,
+                separator,
+            } => load_lines(Cursor::new(input), continuation).unwrap().get_values(
+                &key,
+                multikey,
+                ignore_whitespace,
+                &separator,
+            ),
+            command => panic!("not a get: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_get() {
+        assert_eq!(
+            get_values(SSH_TEST, &["get", "-s", " ", "StrictHostKeyChecking"]).unwrap(),
+            vec!["no"]
+        );
+        assert_eq!(
+            get_values(SSH_TEST, &["get", "-s", " ", "-w", "User"]).unwrap(),
+            vec!["Administrator"]
+        );
+        assert!(get_values(SSH_TEST, &["get", "-s", " ", "User"])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_get_multikey() {
+        assert!(matches!(
+            get_values(SSH_TEST, &["get", "-s", " ", "IdentityFile"]),
+            Err(LinesEditorError::MultipleCandidates)
+        ));
+        assert_eq!(
+            get_values(SSH_TEST, &["get", "-s", " ", "-m", "IdentityFile"]).unwrap(),
+            vec!["~/.ssh/foo", "~/.ssh/bar"]
+        );
+    }
+
+    #[test]
+    fn test_contains_line() -> FinalResult {
+        let pattern = lines_editor::line_pattern("Host *.foo.example.com", false);
+        assert!(lines_editor::contains_match(Cursor::new(SSH_TEST), &pattern, false)?);
+
+        let pattern = lines_editor::line_pattern("User Administrator", false);
+        assert!(!lines_editor::contains_match(Cursor::new(SSH_TEST), &pattern, false)?);
 
+        let pattern = lines_editor::line_pattern("User Administrator", true);
+        assert!(lines_editor::contains_match(Cursor::new(SSH_TEST), &pattern, false)?);
         Ok(())
     }
 
     #[test]
-    fn test_edit_line_pair_absent_multiple_candidates() {
-        let err = stable_pedit(
-            "foo = 1\nbaz = 2\nbaz = 2",
-            &["line-pair", "baz = 2", "absent"],
-        )
-        .unwrap_err();
+    fn test_contains_pair_continuation() -> FinalResult {
+        let input = "CFLAGS = -O2 \\\n    -Wall\nLDFLAGS = -lm\n";
+        let separator = Regex::new(r#"(\s*=\s*)"#)?;
+
+        let (key, value) = lines_editor::split_pair("CFLAGS=-O2     -Wall", &separator)?;
+        let pattern = lines_editor::pair_pattern(key, value, false, &separator);
+        assert!(lines_editor::contains_match(Cursor::new(input), &pattern, true)?);
+        assert!(!lines_editor::contains_match(Cursor::new(input), &pattern, false)?);
+
+        let (key, value) = lines_editor::split_pair("LDFLAGS=-lm", &separator)?;
+        let pattern = lines_editor::pair_pattern(key, value, false, &separator);
+        assert!(lines_editor::contains_match(Cursor::new(input), &pattern, true)?);
+        Ok(())
+    }
 
-        assert_eq!(&err.to_string(), "Multiple candidates found");
+    #[test]
+    fn test_pairs() -> FinalResult {
+        let input = "# comment = here\nfoo = 1\n\n  bar=2 \\\n  3\nfoo= 4\nnot a pair\n";
+        let editor = LinesEditor::load_continued(Cursor::new(input))?;
+        let separator = Regex::new(r#"(\s*=\s*)"#)?;
+
+        let pairs = editor
+            .pairs(true, &separator, Some("#"))
+            .into_iter()
+            .map(|pair| (pair.line, pair.key, pair.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pairs,
+            vec![
+                (2, "foo".to_owned(), "1".to_owned()),
+                (4, "bar".to_owned(), "2   3".to_owned()),
+                (6, "foo".to_owned(), "4".to_owned()),
+            ]
+        );
+        Ok(())
     }
 }
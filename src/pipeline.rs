@@ -0,0 +1,548 @@
+use cotton::prelude::result::Result as PResult;
+use cotton::prelude::*;
+
+use crate::editor::EditStatus;
+use crate::encoding::{self, Encoding};
+use crate::files;
+use crate::lines_editor::StreamEdit;
+use crate::{Cli, Edit, EditError};
+use diff::Result::*;
+use serde::Serialize;
+use std::io::Cursor;
+use std::time::Duration;
+
+/// Line numbers (starting from 1) of lines removed from input and added to output
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ChangedLines {
+    removed: Vec<usize>,
+    added: Vec<usize>,
+}
+
+impl ChangedLines {
+    pub fn new(input: &str, output: &str) -> ChangedLines {
+        let mut changed_lines = ChangedLines::default();
+        let mut input_line = 0;
+        let mut output_line = 0;
+
+        for diff in diff::lines(input, output) {
+            match diff {
+                Left(_) => {
+                    input_line += 1;
+                    changed_lines.removed.push(input_line);
+                }
+                Both(..) => {
+                    input_line += 1;
+                    output_line += 1;
+                }
+                Right(_) => {
+                    output_line += 1;
+                    changed_lines.added.push(output_line);
+                }
+            }
+        }
+        changed_lines
+    }
+}
+
+/// Status of an applied edit with lines it changed; lines are only known if input was buffered for diff or report
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub status: EditStatus,
+    pub changed_lines: ChangedLines,
+}
+
+/// Edit applied in memory that is yet to be written out
+pub struct PendingEdit {
+    pub outcome: Outcome,
+    /// Decoded input the edit was applied to if it was buffered for diff, report or decoding
+    pub input_data: Option<String>,
+    pub output_data: String,
+    /// Whether input started with byte order mark that has to be written back
+    bom: bool,
+    /// Lock on the output file held until the edit is written
+    _lock: Option<files::FileLock>,
+}
+
+impl PendingEdit {
+    /// Prints difference between input and output if the input was buffered and the edit changed it; given file is
+    /// printed as a header
+    pub fn print_diff(&self, file: Option<&Path>) {
+        let input_data = match self.input_data.as_ref() {
+            Some(input_data) if self.outcome.status.has_changed() => input_data,
+            _ => return,
+        };
+
+        // Print whole diff at once so it does not interleave with diffs of files edited in parallel
+        let mut diff_output = String::new();
+        if let Some(file) = file {
+            diff_output.push_str(&format!("--- {}\n", file.display()));
+        }
+        for diff in diff::lines(input_data, &self.output_data) {
+            diff_output.push_str(&match diff {
+                Left(line) => format!("- {}\n", line),
+                Both(line, _) => format!("  {}\n", line),
+                Right(line) => format!("+ {}\n", line),
+            });
+        }
+        eprint!("{}", diff_output);
+    }
+}
+
+/// Applies edit to given input file or standard input and writes the result to given output file or standard output
+pub fn edit_file(
+    args: &Cli,
+    edit: &Edit,
+    input_file: Option<&Path>,
+    output_file: Option<&Path>,
+    multiple_files: bool,
+) -> Result<Outcome, EditError> {
+    if let Some((stream_edit, continuation)) = self::stream_edit(args, edit, output_file)? {
+        return stream_edit_file(args, &stream_edit, continuation, input_file, output_file);
+    }
+
+    let pending = prepare_edit(args, edit, input_file, output_file)?;
+    if args.diff {
+        pending.print_diff(input_file.filter(|_| multiple_files));
+    }
+    write_edit(args, input_file, output_file, &pending)?;
+    run_on_change(args, output_file, &pending.outcome.status)?;
+    Ok(pending.outcome)
+}
+
+/// Takes lock on the output file if requested and the file is going to be written
+fn lock_output(
+    args: &Cli,
+    output_file: Option<&Path>,
+) -> Result<Option<files::FileLock>, EditError> {
+    output_file
+        .filter(|_| args.lock && !args.check)
+        .map(|file| files::lock(file, Duration::from_secs(args.lock_timeout)))
+        .transpose()
+        .map_err(EditError::Io)
+}
+
+/// Opens given input file or standard input returning it without byte order mark and whether the mark was present
+fn open_input(args: &Cli, input_file: Option<&Path>) -> Result<(Box<dyn Read>, bool), EditError> {
+    let input = input_file
+        .map(|file| {
+            match (
+                File::open(file).map(|f| Box::new(f) as Box<dyn Read>),
+                args.create,
+            ) {
+                (Err(err), true) if err.kind() == std::io::ErrorKind::NotFound => {
+                    Ok(Box::new(Cursor::new(String::new())) as Box<dyn Read>)
+                }
+                (result, _) => result,
+            }
+        })
+        .transpose()
+        .problem_while("opening file for reading")
+        .map_err(EditError::Io)?
+        .unwrap_or_else(|| Box::new(stdin()) as Box<dyn Read>);
+
+    // Byte order mark is kept out of edited lines so it does not get in the way of matching and placement
+    args.encoding
+        .strip_bom(input)
+        .map_err(|err| EditError::read(err, "reading input data"))
+}
+
+/// Returns streaming variant of the edit with continuation flag if streaming was requested and the edit and other
+/// options allow it
+///
+/// Output to standard output is buffered so that nothing is printed if the edit or validation fails.
+fn stream_edit(
+    args: &Cli,
+    edit: &Edit,
+    output_file: Option<&Path>,
+) -> Result<Option<(StreamEdit, bool)>, EditError> {
+    if !args.stream {
+        return Ok(None);
+    }
+
+    let stream_edit = if args.diff
+        || args.report.is_some()
+        || args.encoding != Encoding::Utf8
+        || (output_file.is_none() && !args.check)
+    {
+        None
+    } else {
+        match edit {
+            Edit::Line {
+                value,
+                ignore_whitespace,
+                continuation,
+                ensure,
+            } => StreamEdit::line(value, *ignore_whitespace, ensure)
+                .map(|stream_edit| (stream_edit, *continuation)),
+            Edit::LinePair {
+                pair,
+                multikey,
+                ignore_whitespace,
+                continuation,
+                separator,
+                ensure,
+            } => StreamEdit::pair(pair, *multikey, *ignore_whitespace, separator, ensure)?
+                .map(|stream_edit| (stream_edit, *continuation)),
+            _ => None,
+        }
+    };
+
+    if stream_edit.is_none() {
+        info!("Edit can not be streamed; reading whole input to memory");
+    }
+    Ok(stream_edit)
+}
+
+/// Whether the edit did not change the output file that is also its input so the file does not need to be written
+fn unchanged_in_place(
+    input_file: Option<&Path>,
+    output_file: Option<&Path>,
+    status: &EditStatus,
+) -> bool {
+    !status.has_changed() && input_file == output_file && output_file.is_some_and(Path::exists)
+}
+
+/// Applies edit reading input line by line and writing output as it goes; file edited in place is only replaced if
+/// it changed
+fn stream_edit_file(
+    args: &Cli,
+    stream_edit: &StreamEdit,
+    continuation: bool,
+    input_file: Option<&Path>,
+    output_file: Option<&Path>,
+) -> Result<Outcome, EditError> {
+    let _lock = lock_output(args, output_file)?;
+    let (input, bom) = open_input(args, input_file)?;
+
+    let status = match output_file.filter(|_| !args.check) {
+        None => stream_edit
+            .apply(input, std::io::sink(), continuation)
+            .map_err(|err| EditError::read(err, "streaming edit"))??,
+        Some(file) => {
+            let mut output =
+                files::AtomicFile::create(file, &args.new_file()).map_err(EditError::Io)?;
+            if bom {
+                output
+                    .write_all(encoding::BOM)
+                    .problem_while("writing temporary file")
+                    .map_err(EditError::Io)?;
+            }
+            let status = stream_edit
+                .apply(input, &mut output, continuation)
+                .map_err(|err| EditError::read(err, "streaming edit"))??;
+
+            // Unchanged file is left as is by dropping the temporary file
+            if !unchanged_in_place(input_file, output_file, &status) {
+                output
+                    .commit(args.validate.as_deref())
+                    .problem_while("writing output file")
+                    .map_err(EditError::Io)?;
+            }
+            status
+        }
+    };
+    info!("Edit result: {}", status);
+
+    run_on_change(args, output_file, &status)?;
+    Ok(Outcome {
+        status,
+        changed_lines: ChangedLines::default(),
+    })
+}
+
+/// Applies edit to given input file or standard input in memory holding lock on the output file until it is written
+pub fn prepare_edit(
+    args: &Cli,
+    edit: &Edit,
+    input_file: Option<&Path>,
+    output_file: Option<&Path>,
+) -> Result<PendingEdit, EditError> {
+    let lock = lock_output(args, output_file)?;
+    let mut input_data = None;
+    let (mut input, bom) = open_input(args, input_file)?;
+
+    if args.diff || args.report.is_some() || args.encoding != Encoding::Utf8 {
+        let mut data = Vec::new();
+        input
+            .read_to_end(&mut data)
+            .map_err(|err| EditError::read(err, "reading input data"))?;
+
+        input_data = Some(
+            args.encoding
+                .decode(data)
+                .problem_while("decoding input data")?,
+        );
+        input = Box::new(Cursor::new(input_data.as_ref().unwrap()));
+    }
+
+    let (edited, status) = crate::edit(input, edit.clone())?;
+    let output_data = edited.to_string();
+
+    info!("Edit result: {}", status);
+
+    let changed_lines = match input_data.as_ref() {
+        Some(input_data) if status.has_changed() => ChangedLines::new(input_data, &output_data),
+        _ => ChangedLines::default(),
+    };
+
+    Ok(PendingEdit {
+        outcome: Outcome {
+            status,
+            changed_lines,
+        },
+        input_data,
+        output_data,
+        bom,
+        _lock: lock,
+    })
+}
+
+/// Writes edited data to given output file or standard output unless in check mode or the file edited in place did
+/// not change
+pub fn write_edit(
+    args: &Cli,
+    input_file: Option<&Path>,
+    output_file: Option<&Path>,
+    pending: &PendingEdit,
+) -> Result<(), EditError> {
+    if args.check || unchanged_in_place(input_file, output_file, &pending.outcome.status) {
+        return Ok(());
+    }
+
+    let output_data = args
+        .encoding
+        .encode(&pending.output_data, pending.bom)
+        .problem_while("encoding output data")?;
+    let output_data = output_data.as_ref();
+    let validate = args.validate.as_deref();
+
+    match output_file {
+        Some(file) => files::write_atomic(file, output_data, validate, &args.new_file())
+            .problem_while("writing output file"),
+        None => validate
+            .map_or(Ok(()), |command| files::validate_data(output_data, command))
+            .and_then(|()| {
+                stdout()
+                    .write_all(output_data)
+                    .problem_while("writing output")
+            }),
+    }
+    .map_err(EditError::Io)
+}
+
+/// Runs on change command if file was changed and not in check mode
+pub fn run_on_change(
+    args: &Cli,
+    output_file: Option<&Path>,
+    status: &EditStatus,
+) -> Result<(), EditError> {
+    match args.on_change.as_deref() {
+        Some(command) if !args.check && status.has_changed() => {
+            on_change(command, output_file, status).map_err(EditError::Io)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Runs command with shell passing edited file path and edit status via environment variables
+fn on_change(command: &str, file: Option<&Path>, status: &EditStatus) -> PResult<()> {
+    let shell = if cfg!(windows) {
+        cmd!("cmd", "/C", command)
+    } else {
+        cmd!("sh", "-c", command)
+    };
+
+    debug!("Running on change command {:?}", command);
+    let output = shell
+        .env(
+            "PEDIT_FILE",
+            file.map(|file| file.as_os_str()).unwrap_or_default(),
+        )
+        .env("PEDIT_STATUS", status.to_string())
+        // Keep standard output for edited content
+        .stdout_to_stderr()
+        .unchecked()
+        .run()
+        .problem_while_with(|| format!("running on change command {:?}", command))?;
+
+    if !output.status.success() {
+        return problem!(
+            "On change command {:?} failed with status code {}",
+            command,
+            output
+                .status
+                .code()
+                .map_or_else(|| "unknown".to_owned(), |code| code.to_string())
+        );
+    }
+    Ok(())
+}
+
+/// Message of the problem without backtrace
+pub fn problem_message(problem: &Problem) -> String {
+    let message = problem.to_string();
+    match message.find("\n--- Cause\n") {
+        Some(end) => message[..end].to_owned(),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::PresentStatus;
+    use crate::lines_editor::LinesEditorError;
+    use crate::tests::{pedit, SSH_TEST};
+    use crate::Command;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_on_change_environment() -> FinalResult {
+        let status = EditStatus::Present(PresentStatus::InsertedPlacement);
+        on_change(
+            r#"test "$PEDIT_FILE:$PEDIT_STATUS" = "/etc/hosts:value was inserted""#,
+            Some(Path::new("/etc/hosts")),
+            &status,
+        )?;
+
+        let err = on_change("exit 3", None, &status).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with(r#"On change command "exit 3" failed with status code 3"#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_lines() {
+        let changed_lines = ChangedLines::new("a\nb\nc\n", "a\nB\nc\nd\n");
+        assert_eq!(changed_lines.removed, vec![2]);
+        assert_eq!(changed_lines.added, vec![2, 4]);
+    }
+
+    #[test]
+    fn test_stream_matches_buffered() -> FinalResult {
+        let stream_pedit = |input: &str, args: &[&str]| {
+            let cli = Cli::from_iter_safe(
+                Some("pedit")
+                    .iter()
+                    .chain(["--stream"].iter())
+                    .chain(args.iter()),
+            )
+            .unwrap();
+            let edit = match &cli.command {
+                Command::Edit(edit) => edit,
+                command => panic!("not an edit: {:?}", command),
+            };
+            let (stream_edit, continuation) =
+                stream_edit(&cli, edit, Some(Path::new("ssh_config")))
+                    .unwrap()
+                    .expect("not streamable");
+            let mut output = Vec::new();
+            let status = stream_edit
+                .apply(Cursor::new(input), &mut output, continuation)
+                .unwrap();
+            status.map(|status| (String::from_utf8(output).unwrap(), status))
+        };
+
+        for args in &[
+            &["line", "Compression yes", "present", "at-end"][..],
+            &["line", "StrictHostKeyChecking no", "present", "at-end"],
+            &["line", "StrictHostKeyChecking no", "absent"],
+            &[
+                "line-pair",
+                "-s",
+                " ",
+                "StrictHostKeyChecking yes",
+                "present",
+                "at-end",
+            ],
+            &[
+                "line-pair",
+                "-s",
+                " ",
+                "StrictHostKeyChecking no",
+                "present",
+                "at-end",
+            ],
+            &[
+                "line-pair",
+                "-s",
+                " ",
+                "-m",
+                "IdentityFile ~/.ssh/baz",
+                "present",
+                "at-end",
+            ],
+            &["line-pair", "-s", " ", "-w", "User Administrator", "absent"],
+        ] {
+            let (output, status) = stream_pedit(SSH_TEST, args)?;
+            let (expected_output, expected_status) = pedit(SSH_TEST, args)?;
+            assert_eq!(output, expected_output);
+            assert_eq!(status.to_string(), expected_status.to_string());
+        }
+
+        assert!(matches!(
+            stream_pedit(
+                SSH_TEST,
+                &[
+                    "line-pair",
+                    "-s",
+                    " ",
+                    "IdentityFile ~/.ssh/baz",
+                    "present",
+                    "at-end"
+                ]
+            ),
+            Err(LinesEditorError::MultipleCandidates)
+        ));
+
+        let (output, _) = stream_pedit(
+            "a = 1 \\\n  2\nb = 3",
+            &["line", "-l", "a = 1   2", "absent"],
+        )?;
+        assert_eq!(output, "b = 3\n");
+
+        let cli = Cli::from_iter_safe(&["pedit", "--stream", "line", "foo", "present", "at-top"])?;
+        match &cli.command {
+            Command::Edit(edit) => {
+                let output_file = Some(Path::new("ssh_config"));
+                assert!(stream_edit(&cli, edit, output_file)?.is_none());
+            }
+            command => panic!("not an edit: {:?}", command),
+        }
+
+        // Standard output is buffered so that failed edit prints nothing
+        let cli = Cli::from_iter_safe(&["pedit", "--stream", "line", "foo", "absent"])?;
+        match &cli.command {
+            Command::Edit(edit) => assert!(stream_edit(&cli, edit, None)?.is_none()),
+            command => panic!("not an edit: {:?}", command),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_unchanged_output() -> FinalResult {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("tpl");
+        let output = dir.path().join("out").join("x");
+        fs::write(&input, "bar\n")?;
+
+        let cli = Cli::from_iter_safe(&[
+            OsStr::new("pedit"),
+            OsStr::new("--stream"),
+            OsStr::new("--output"),
+            output.as_os_str(),
+            OsStr::new("line"),
+            OsStr::new("foo"),
+            OsStr::new("absent"),
+        ])?;
+        let edit = match &cli.command {
+            Command::Edit(edit) => edit,
+            command => panic!("not an edit: {:?}", command),
+        };
+        let outcome = edit_file(&cli, edit, Some(&input), cli.output.as_deref(), false)?;
+        assert!(!outcome.status.has_changed());
+        assert_eq!(fs::read_to_string(&output)?, "bar\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+use crate::editor::*;
+use crate::lines_editor::{
+    ends_with_continuation, place_line, single_position, write_lines, LinesEditorError,
+};
+
+use cotton::prelude::*;
+
+const NEW_LINE: &str = "\n";
+const DEFAULT_SEPARATOR: &str = "=";
+
+/// Editor for Java `.properties` files operating on logical lines
+#[derive(Debug)]
+pub struct PropertiesEditor {
+    /// Logical lines with physical lines joined with new line character
+    lines: Vec<String>,
+    escape_unicode: bool,
+}
+
+/// Property parsed from logical line
+#[derive(Debug)]
+struct Property<'l> {
+    key_raw: &'l str,
+    separator: &'l str,
+    key: String,
+    value: String,
+}
+
+fn is_comment_or_blank(line: &str) -> bool {
+    let line = line.trim_start();
+    line.is_empty() || line.starts_with('#') || line.starts_with('!')
+}
+
+/// Joins physical lines of logical line dropping line continuation characters and leading white space of continued lines
+fn join_continuations(raw: &str) -> String {
+    let mut lines = raw.split('\n').peekable();
+    let mut logical = String::new();
+    let mut first = true;
+
+    while let Some(line) = lines.next() {
+        let line = if first { line } else { line.trim_start() };
+        first = false;
+
+        if lines.peek().is_some() {
+            logical.push_str(&line[..line.len() - 1]);
+        } else {
+            logical.push_str(line);
+        }
+    }
+    logical
+}
+
+fn parse_property(logical: &str) -> Option<Property<'_>> {
+    if is_comment_or_blank(logical) {
+        return None;
+    }
+
+    let line = logical.trim_start();
+    let mut chars = line.char_indices();
+    let mut key_end = line.len();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '=' | ':' => {
+                key_end = index;
+                break;
+            }
+            c if c.is_whitespace() => {
+                key_end = index;
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    let key_raw = &line[..key_end];
+    let rest = line[key_end..].trim_start();
+    let rest = rest
+        .strip_prefix(|c| c == '=' || c == ':')
+        .unwrap_or(rest)
+        .trim_start();
+    let value_start = line.len() - rest.len();
+
+    Some(Property {
+        key_raw,
+        separator: &line[key_end..value_start],
+        key: unescape(key_raw),
+        value: unescape(rest),
+    })
+}
+
+fn unescape(raw: &str) -> String {
+    let mut units: Vec<u16> = Vec::new();
+    let mut chars = raw.chars();
+    let mut buf = [0; 2];
+
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next() {
+                Some('t') => '\t',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('f') => '\x0c',
+                Some('u') => {
+                    let hex = chars.by_ref().take(4).collect::<String>();
+                    if let Ok(unit) = u16::from_str_radix(&hex, 16) {
+                        // Surrogate pairs are decoded together below
+                        units.push(unit);
+                        continue;
+                    }
+                    units.extend("\\u".encode_utf16());
+                    units.extend(hex.encode_utf16());
+                    continue;
+                }
+                Some(c) => c,
+                None => continue,
+            }
+        } else {
+            c
+        };
+        units.extend_from_slice(c.encode_utf16(&mut buf));
+    }
+
+    String::from_utf16_lossy(&units)
+}
+
+fn escape(value: &str, key: bool, escape_unicode: bool) -> String {
+    let mut escaped = String::new();
+
+    for (index, c) in value.chars().enumerate() {
+        match c {
+            '\\' => escaped.push_str(r"\\"),
+            '\t' => escaped.push_str(r"\t"),
+            '\n' => escaped.push_str(r"\n"),
+            '\r' => escaped.push_str(r"\r"),
+            '\x0c' => escaped.push_str(r"\f"),
+            ' ' if key || index == 0 => escaped.push_str(r"\ "),
+            '=' | ':' if key => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '#' | '!' if index == 0 => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if escape_unicode && !c.is_ascii() => {
+                let mut buf = [0; 2];
+                for unit in c.encode_utf16(&mut buf) {
+                    escaped.push_str(&format!(r"\u{:04X}", unit));
+                }
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+impl PropertiesEditor {
+    pub fn load<R: Read>(
+        data: R,
+        escape_unicode: bool,
+    ) -> Result<PropertiesEditor, std::io::Error> {
+        let mut lines: Vec<String> = Vec::new();
+        let mut continued = false;
+
+        for line in BufReader::new(data).lines() {
+            let line = line?;
+
+            if continued {
+                let logical = lines.last_mut().unwrap();
+                logical.push_str(NEW_LINE);
+                logical.push_str(&line);
+            } else {
+                lines.push(line);
+            }
+
+            let logical = lines.last().unwrap();
+            continued = if continued || !is_comment_or_blank(logical) {
+                ends_with_continuation(logical)
+            } else {
+                false
+            };
+        }
+
+        Ok(PropertiesEditor {
+            lines,
+            escape_unicode,
+        })
+    }
+
+    /// Indices of logical lines with property of given key
+    fn find_key(&self, key: &str) -> Vec<usize> {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(|(_, raw)| {
+                matches!(parse_property(&join_continuations(raw)), Some(property) if property.key == key)
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Separator used by first property in the file
+    fn separator(&self) -> String {
+        self.lines
+            .iter()
+            .find_map(|raw| {
+                parse_property(&join_continuations(raw))
+                    .map(|property| property.separator.to_owned())
+            })
+            .filter(|separator| !separator.is_empty())
+            .unwrap_or_else(|| DEFAULT_SEPARATOR.to_owned())
+    }
+
+    fn property_line(&self, key_raw: &str, separator: &str, value: &str) -> String {
+        format!(
+            "{}{}{}",
+            key_raw,
+            separator,
+            escape(value, false, self.escape_unicode)
+        )
+    }
+
+    fn replaced(&mut self, key: &str, value: &str) -> Result<ReplaceStatus, LinesEditorError> {
+        let index = match self.find_key(key).as_slice() {
+            [] => return Err(LinesEditorError::NotApplicable(value.to_owned())),
+            [index] => *index,
+            _ => return Err(LinesEditorError::MultipleCandidates),
+        };
+
+        let logical = join_continuations(&self.lines[index]);
+        let property = parse_property(&logical).expect("property line");
+        if property.value == value {
+            return Ok(ReplaceStatus::AlreadyPresent);
+        }
+
+        let indent = &logical[..logical.len() - logical.trim_start().len()];
+        let separator = if property.separator.is_empty() {
+            self.separator()
+        } else {
+            property.separator.to_owned()
+        };
+        let line = self.property_line(
+            &format!("{}{}", indent, property.key_raw),
+            &separator,
+            value,
+        );
+        self.lines[index] = line;
+
+        Ok(ReplaceStatus::Replaced)
+    }
+
+    fn present(
+        &mut self,
+        key: &str,
+        value: &str,
+        placement: &Placement,
+    ) -> Result<PresentStatus, LinesEditorError> {
+        let line = self.property_line(
+            &escape(key, true, self.escape_unicode),
+            &self.separator(),
+            value,
+        );
+
+        place_line(&mut self.lines, line, placement, |lines, anchor| {
+            single_position(lines, |raw| anchor.is_match(&join_continuations(raw)))
+        })
+    }
+
+    fn absent(&mut self, key: &str, value: &str) -> Result<AbsentStatus, LinesEditorError> {
+        let matching = self
+            .find_key(key)
+            .into_iter()
+            .filter(|index| {
+                matches!(
+                    parse_property(&join_continuations(&self.lines[*index])),
+                    Some(property) if property.value == value
+                )
+            })
+            .collect::<Vec<_>>();
+
+        match matching.as_slice() {
+            [] => Ok(AbsentStatus::AlreadyAbsent),
+            [index] => {
+                self.lines.remove(*index);
+                Ok(AbsentStatus::Removed)
+            }
+            _ => Err(LinesEditorError::MultipleCandidates),
+        }
+    }
+
+    pub fn edit_property(
+        &mut self,
+        key: String,
+        value: String,
+        ensure: Ensure,
+    ) -> Result<EditStatus, LinesEditorError> {
+        let status = match ensure {
+            Ensure::Present { placement } => {
+                info!("Ensuring property {:?} has value {:?}", key, value);
+                match self.replaced(&key, &value) {
+                    Err(LinesEditorError::NotApplicable(_)) => {
+                        self.present(&key, &value, &placement)?.into()
+                    }
+                    Err(err) => return Err(err),
+                    Ok(status) => status.into(),
+                }
+            }
+            Ensure::Absent => {
+                info!(
+                    "Ensuring property {:?} with value {:?} is absent",
+                    key, value
+                );
+                self.absent(&key, &value)?.into()
+            }
+        };
+
+        debug!("Edit property:\n{:?}:\n{:#?}", status, self);
+        Ok(status)
+    }
+}
+
+impl fmt::Display for PropertiesEditor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_lines(f, &self.lines)
+    }
+}